@@ -1,26 +1,249 @@
-use std::collections::HashMap;
+use std::cmp;
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
 
-use anyhow::{Context, Result};
-use reqwest::blocking;
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::NaiveDate;
+use flate2::read::{DeflateDecoder, ZlibDecoder};
+use ordered_float::OrderedFloat;
+use reqwest::header::{
+    ACCEPT_ENCODING, CONTENT_ENCODING, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, LINK,
+};
+use reqwest::{blocking, Proxy, StatusCode, Url};
 use serde::{Deserialize, Serialize};
 use std::convert::TryInto;
 
+use crate::cache::{Cache, Entry, Validators};
+use crate::prefetch::Prefetcher;
+use crate::ratelimit::RateLimiter;
+
+/// Number of prefetched station maps kept in memory.
+const PREFETCH_CAPACITY: usize = 8;
+
 //
 // StationMap
 //
 
-#[derive(Debug)]
+/// Stations and segments are in the order of the api, so the same infrastructure always results
+/// in the same station map.
+#[derive(Debug, Clone)]
 pub struct StationMap {
     pub id: u64,
     pub name: String,
+    pub year: Option<u32>,
     pub stations: Vec<Station>,
     pub segments: Vec<Segment>,
+    pub warnings: Vec<String>,
 }
 
 impl StationMap {
+    /// Converts `infrastruktur`, a segment with an unknown station is an error unless
+    /// `lenient` is set, then the segment is left out with a warning.
+    pub fn from_infrastruktur(infrastruktur: Infrastruktur, lenient: bool) -> Result<StationMap> {
+        if lenient {
+            infrastruktur.into_station_map(true)
+        } else {
+            infrastruktur.try_into()
+        }
+    }
+
     pub fn coordinates(&self) -> Vec<(f64, f64)> {
         self.stations.iter().map(|station| station.coord).collect()
     }
+
+    /// The bounding box of all stations.
+    pub fn extent(&self) -> Extent {
+        calc_extent(&self.coordinates())
+    }
+
+    /// The distances between all stations, `matrix[i][j]` is the distance from station `i` to
+    /// station `j`. Needs O(n²) memory.
+    pub fn distance_matrix(&self) -> Vec<Vec<f64>> {
+        self.stations
+            .iter()
+            .map(|from| self.stations.iter().map(|to| from.distance(to)).collect())
+            .collect()
+    }
+
+    /// The stations connected to the station `ds100` by a segment, each station once. If not
+    /// `undirected`, only the `to` stations of segments starting at `ds100` are neighbors.
+    pub fn neighbors(&self, ds100: &str, undirected: bool) -> Vec<&Station> {
+        let mut neighbors: Vec<&Station> = Vec::new();
+        for segment in &self.segments {
+            let neighbor = if segment.from.ds100 == ds100 {
+                &segment.to
+            } else if undirected && segment.to.ds100 == ds100 {
+                &segment.from
+            } else {
+                continue;
+            };
+            if neighbor.ds100 != ds100 && !neighbors.iter().any(|n| n.ds100 == neighbor.ds100) {
+                neighbors.push(neighbor);
+            }
+        }
+        neighbors
+    }
+
+    /// The summed length in km of all segments, see `route_length_km`.
+    pub fn total_length_km(&self) -> f64 {
+        self.segments.iter().map(Segment::length_km).sum()
+    }
+
+    /// The summed length in km of all segments of the route `routenumber`. The api has no
+    /// segment lengths, so this is the great-circle approximation of `Segment::length_km`.
+    pub fn route_length_km(&self, routenumber: u32) -> f64 {
+        self.segments
+            .iter()
+            .filter(|segment| segment.routenumber == routenumber)
+            .map(Segment::length_km)
+            .sum()
+    }
+
+    /// The connected components of the network as indices into `stations`, the largest
+    /// component first. If not `undirected`, these are the strongly connected components, every
+    /// station of one is reachable from every other one along the segment directions. Segments
+    /// to stations that are not listed are ignored.
+    pub fn components(&self, undirected: bool) -> Vec<Vec<usize>> {
+        let edges = self.edges();
+        let mut components = if undirected {
+            undirected_components(self.stations.len(), &edges)
+        } else {
+            strong_components(self.stations.len(), &edges)
+        };
+        components.sort_by(|a, b| b.len().cmp(&a.len()).then(a[0].cmp(&b[0])));
+        components
+    }
+
+    /// The segments as `(from, to)` indices into `stations`.
+    fn edges(&self) -> Vec<(usize, usize)> {
+        let indices: BTreeMap<&str, usize> = self
+            .stations
+            .iter()
+            .enumerate()
+            .map(|(index, station)| (station.ds100.as_str(), index))
+            .collect();
+        self.segments
+            .iter()
+            .filter_map(|segment| {
+                Some((
+                    *indices.get(segment.from.ds100.as_str())?,
+                    *indices.get(segment.to.ds100.as_str())?,
+                ))
+            })
+            .collect()
+    }
+
+    /// The number of segments per route number, sorted by count descending.
+    pub fn routes(&self) -> Vec<(u32, usize)> {
+        let mut counts: BTreeMap<u32, usize> = BTreeMap::new();
+        for segment in &self.segments {
+            *counts.entry(segment.routenumber).or_insert(0) += 1;
+        }
+        let mut routes: Vec<(u32, usize)> = counts.into_iter().collect();
+        routes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        routes
+    }
+
+    /// The stations no segment starts or ends at.
+    pub fn isolated_stations(&self) -> Vec<&Station> {
+        let connected: HashSet<&str> = self
+            .segments
+            .iter()
+            .flat_map(|segment| [segment.from.ds100.as_str(), segment.to.ds100.as_str()])
+            .collect();
+        self.stations
+            .iter()
+            .filter(|station| !connected.contains(station.ds100.as_str()))
+            .collect()
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            stations: self.stations.len(),
+            segments: self.segments.len(),
+            routes: self.routes().len(),
+            isolated: self.isolated_stations().len(),
+            extent: self.extent(),
+        }
+    }
+
+    /// Merges `others` into this map. Stations are merged by `ds100`, the coordinates of the
+    /// first map win and differing coordinates are reported as warnings. Segments are
+    /// concatenated.
+    pub fn merge(mut self, others: Vec<StationMap>) -> StationMap {
+        let mut coords: BTreeMap<String, (f64, f64)> = self
+            .stations
+            .iter()
+            .map(|station| (station.ds100.clone(), station.coord))
+            .collect();
+
+        for other in others {
+            for station in other.stations {
+                match coords.get(&station.ds100) {
+                    Some(&coord) if coord != station.coord => self.warnings.push(format!(
+                        "Station '{}' has the coordinates {:?} in '{}' but {:?} in '{}'",
+                        station.ds100, coord, self.name, station.coord, other.name
+                    )),
+                    Some(_) => {}
+                    None => {
+                        coords.insert(station.ds100.clone(), station.coord);
+                        self.stations.push(station);
+                    }
+                }
+            }
+            self.segments.extend(other.segments);
+            self.warnings.extend(other.warnings);
+            self.name = format!("{} + {}", self.name, other.name);
+        }
+        self
+    }
+
+    /// The stations inside `bbox` and the segments with at least one of their stations inside.
+    /// Stations outside `bbox` stay referenced by their segments, but are not listed.
+    pub fn filter_bbox(&self, bbox: Extent) -> StationMap {
+        StationMap {
+            id: self.id,
+            name: self.name.clone(),
+            year: self.year,
+            stations: self
+                .stations
+                .iter()
+                .filter(|station| bbox.contains(station.coord))
+                .cloned()
+                .collect(),
+            segments: self
+                .segments
+                .iter()
+                .filter(|segment| {
+                    bbox.contains(segment.from.coord) || bbox.contains(segment.to.coord)
+                })
+                .cloned()
+                .collect(),
+            warnings: self.warnings.clone(),
+        }
+    }
+}
+
+/// Counts of a station map, see `StationMap::stats`.
+#[derive(Debug, Clone)]
+pub struct Stats {
+    pub stations: usize,
+    pub segments: usize,
+    /// The number of distinct route numbers.
+    pub routes: usize,
+    /// The number of stations without segments.
+    pub isolated: usize,
+    /// The bounding box of all stations.
+    pub extent: Extent,
 }
 
 #[derive(Debug, Clone)]
@@ -30,50 +253,881 @@ pub struct Station {
     pub coord: (f64, f64), // (x, y)
 }
 
-#[derive(Debug)]
+impl Station {
+    /// The straight-line distance in coordinate units.
+    pub fn distance(&self, other: &Station) -> f64 {
+        (self.coord.0 - other.coord.0).hypot(self.coord.1 - other.coord.1)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Segment {
     pub from: Station,
     pub to: Station,
     pub routenumber: u32,
+    pub electrified: Option<bool>,
+}
+
+impl Segment {
+    /// The great-circle length in km, for coordinates in degrees (x longitude, y latitude).
+    pub fn length_km(&self) -> f64 {
+        let (lon1, lat1) = (
+            self.from.coord.0.to_radians(),
+            self.from.coord.1.to_radians(),
+        );
+        let (lon2, lat2) = (self.to.coord.0.to_radians(), self.to.coord.1.to_radians());
+        let a = ((lat2 - lat1) / 2.0).sin().powi(2)
+            + lat1.cos() * lat2.cos() * ((lon2 - lon1) / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+    }
+}
+
+/// Mean earth radius.
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// The connected components of a graph of `count` nodes, each sorted ascending.
+fn undirected_components(count: usize, edges: &[(usize, usize)]) -> Vec<Vec<usize>> {
+    // union-find
+    let mut parents: Vec<usize> = (0..count).collect();
+    fn root(parents: &mut [usize], mut index: usize) -> usize {
+        while parents[index] != index {
+            parents[index] = parents[parents[index]];
+            index = parents[index];
+        }
+        index
+    }
+    for &(from, to) in edges {
+        let (from, to) = (root(&mut parents, from), root(&mut parents, to));
+        parents[from] = to;
+    }
+
+    let mut components: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for index in 0..count {
+        let root = root(&mut parents, index);
+        components.entry(root).or_default().push(index);
+    }
+    components.into_values().collect()
+}
+
+/// The strongly connected components of a directed graph of `count` nodes, each sorted
+/// ascending. Kosaraju's algorithm with explicit stacks, networks are too deep for recursion.
+fn strong_components(count: usize, edges: &[(usize, usize)]) -> Vec<Vec<usize>> {
+    let mut forward: Vec<Vec<usize>> = vec![Vec::new(); count];
+    let mut backward: Vec<Vec<usize>> = vec![Vec::new(); count];
+    for &(from, to) in edges {
+        forward[from].push(to);
+        backward[to].push(from);
+    }
+
+    // the nodes ordered by when their depth-first search finished
+    let mut finished = Vec::with_capacity(count);
+    let mut visited = vec![false; count];
+    for start in 0..count {
+        if visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        let mut stack = vec![(start, 0)];
+        while let Some((node, next)) = stack.pop() {
+            match forward[node].get(next) {
+                Some(&successor) => {
+                    stack.push((node, next + 1));
+                    if !visited[successor] {
+                        visited[successor] = true;
+                        stack.push((successor, 0));
+                    }
+                }
+                None => finished.push(node),
+            }
+        }
+    }
+
+    // the nodes reachable backwards from the last finished node form a component
+    let mut component_of: Vec<Option<usize>> = vec![None; count];
+    let mut components: Vec<Vec<usize>> = Vec::new();
+    for &start in finished.iter().rev() {
+        if component_of[start].is_some() {
+            continue;
+        }
+        let component = components.len();
+        component_of[start] = Some(component);
+        let mut members = vec![start];
+        let mut stack = vec![start];
+        while let Some(node) = stack.pop() {
+            for &predecessor in &backward[node] {
+                if component_of[predecessor].is_none() {
+                    component_of[predecessor] = Some(component);
+                    members.push(predecessor);
+                    stack.push(predecessor);
+                }
+            }
+        }
+        members.sort_unstable();
+        components.push(members);
+    }
+    components
+}
+
+//
+// Extent
+//
+
+#[derive(Debug, Clone, Copy)]
+pub struct Extent {
+    pub min_x: f64,
+    pub max_x: f64,
+    pub min_y: f64,
+    pub max_y: f64,
+}
+
+impl Extent {
+    pub fn width(&self) -> f64 {
+        self.max_x - self.min_x
+    }
+
+    pub fn height(&self) -> f64 {
+        self.max_y - self.min_y
+    }
+
+    pub fn contains(&self, (x, y): (f64, f64)) -> bool {
+        x >= self.min_x && x <= self.max_x && y >= self.min_y && y <= self.max_y
+    }
+
+    /// Whether the line from `from` to `to` crosses or touches the extent, also if both ends
+    /// are outside.
+    pub fn intersects_line(&self, from: (f64, f64), to: (f64, f64)) -> bool {
+        let bounding_boxes_overlap = from.0.max(to.0) >= self.min_x
+            && from.0.min(to.0) <= self.max_x
+            && from.1.max(to.1) >= self.min_y
+            && from.1.min(to.1) <= self.max_y;
+        if !bounding_boxes_overlap {
+            return false;
+        }
+
+        // a line with overlapping bounding boxes misses the extent only if all corners are on
+        // the same side of it, e.g. a diagonal line passing a corner
+        let side =
+            |(x, y): (f64, f64)| (to.0 - from.0) * (y - from.1) - (to.1 - from.1) * (x - from.0);
+        let corners = [
+            side((self.min_x, self.min_y)),
+            side((self.min_x, self.max_y)),
+            side((self.max_x, self.min_y)),
+            side((self.max_x, self.max_y)),
+        ];
+        !(corners.iter().all(|&side| side > 0.0) || corners.iter().all(|&side| side < 0.0))
+    }
+
+    /// Scales the extent by `factor` around its center.
+    pub fn scaled(&self, factor: f64) -> Extent {
+        self.scaled_xy(factor, factor)
+    }
+
+    /// Scales the width by `factor_x` and the height by `factor_y` around the center.
+    pub fn scaled_xy(&self, factor_x: f64, factor_y: f64) -> Extent {
+        let (dx, dy) = (
+            self.width() * (factor_x - 1.0) / 2.0,
+            self.height() * (factor_y - 1.0) / 2.0,
+        );
+        Extent {
+            min_x: self.min_x - dx,
+            max_x: self.max_x + dx,
+            min_y: self.min_y - dy,
+            max_y: self.max_y + dy,
+        }
+    }
+
+    pub fn translated(&self, dx: f64, dy: f64) -> Extent {
+        Extent {
+            min_x: self.min_x + dx,
+            max_x: self.max_x + dx,
+            min_y: self.min_y + dy,
+            max_y: self.max_y + dy,
+        }
+    }
+}
+
+/// The bounding box of `coords`. Without coordinates it is `0..1` on both axes, an axis all
+/// coordinates share is widened by `0.5` to both sides, so a view always has an area.
+pub fn calc_extent(coords: &[(f64, f64)]) -> Extent {
+    if coords.is_empty() {
+        return Extent {
+            min_x: 0.0,
+            max_x: 1.0,
+            min_y: 0.0,
+            max_y: 1.0,
+        };
+    }
+
+    let x_coords: Vec<OrderedFloat<f64>> =
+        coords.iter().map(|coord| OrderedFloat(coord.0)).collect();
+    let y_coords: Vec<OrderedFloat<f64>> =
+        coords.iter().map(|coord| OrderedFloat(coord.1)).collect();
+
+    let range = |values: &[OrderedFloat<f64>]| {
+        let min = values.iter().min().unwrap().into_inner();
+        let max = values.iter().max().unwrap().into_inner();
+        if min < max {
+            (min, max)
+        } else {
+            (min - 0.5, max + 0.5)
+        }
+    };
+    let (min_x, max_x) = range(&x_coords);
+    let (min_y, max_y) = range(&y_coords);
+    Extent {
+        min_x,
+        max_x,
+        min_y,
+        max_y,
+    }
+}
+
+/// `min_x,min_y,max_x,max_y`, like `--bbox` takes it.
+impl fmt::Display for Extent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{},{}",
+            self.min_x, self.min_y, self.max_x, self.max_y
+        )
+    }
+}
+
+impl FromStr for Extent {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let values = s
+            .split(',')
+            .map(|value| value.trim().parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .with_context(|| format!("Invalid bounding box '{}'", s))?;
+        match values[..] {
+            [min_x, min_y, max_x, max_y] if min_x < max_x && min_y < max_y => Ok(Extent {
+                min_x,
+                max_x,
+                min_y,
+                max_y,
+            }),
+            _ => bail!(
+                "Invalid bounding box '{}', expected 'min_x,min_y,max_x,max_y'",
+                s
+            ),
+        }
+    }
 }
 
 //
 // API
 //
 
-pub fn read_infrastructure_infos(url: &str) -> Result<Vec<InfrastrukturInfo>> {
-    blocking::get(url)
-        .with_context(|| format!("Could not read infrastructure indices from url '{}'", url))?
-        .json()
-        .with_context(|| format!("Could not parse infrastrukturen (json) from url '{}'", url))
-        .map(|mut indices: Vec<InfrastrukturInfo>| {
-            indices.sort_by_key(|k| k.id);
-            indices
+#[derive(Clone)]
+pub struct DataSource {
+    url: String,
+    proxy: Option<String>,
+    timeout: Duration,
+    retries: u32,
+    lenient: bool,
+    compression: bool,
+    client: blocking::Client,
+    cache: Option<Cache>,
+    prefetcher: Prefetcher,
+    rate_limiter: RateLimiter,
+    in_flight: Arc<AtomicUsize>,
+    offline: Arc<Mutex<Option<SystemTime>>>, // age of the cached data shown instead
+}
+
+/// How a `DataSource` talks to the api.
+#[derive(Debug, Clone)]
+pub struct SourceOptions<'a> {
+    /// Overrides the `HTTP_PROXY`/`HTTPS_PROXY`/`NO_PROXY` environment variables.
+    pub proxy: Option<&'a str>,
+    /// At most this many requests are sent per second.
+    pub max_rps: f64,
+    /// Limits connecting plus reading the whole response.
+    pub timeout: Duration,
+    /// How often connection errors and `5xx` responses are retried.
+    pub retries: u32,
+    /// Where responses are cached, `None` disables the cache.
+    pub cache_dir: Option<&'a Path>,
+    /// Leave out segments with unknown stations, see `StationMap::from_infrastruktur`.
+    pub lenient: bool,
+    /// Ask for gzip or deflate compressed responses, off to see the raw payloads.
+    pub compression: bool,
+}
+
+/// An error status the api responded with.
+#[derive(Debug)]
+struct StatusError(StatusCode);
+
+impl StatusError {
+    /// Whether `error` was caused by the response status `status`.
+    fn is(error: &anyhow::Error, status: StatusCode) -> bool {
+        error.chain().any(|cause| {
+            cause
+                .downcast_ref::<StatusError>()
+                .is_some_and(|e| e.0 == status)
         })
+    }
+}
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The api responded with '{}'", self.0)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+/// Whether `error` means the api is unreachable: no connection, no response in time or a
+/// `5xx` response. A `4xx` response is an answer of the api.
+fn is_unreachable(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        if let Some(error) = cause.downcast_ref::<reqwest::Error>() {
+            error.is_connect() || error.is_timeout() || error.is_request()
+        } else if let Some(error) = cause.downcast_ref::<StatusError>() {
+            error.0.is_server_error()
+        } else {
+            false
+        }
+    })
 }
 
+/// Wait before the first retry of a failed request, doubled for every further retry.
+const RETRY_BACKOFF: Duration = Duration::from_millis(250);
+
+impl DataSource {
+    /// Creates a data source for the given api url.
+    pub fn new(url: &str, options: &SourceOptions) -> Result<Self> {
+        let SourceOptions {
+            proxy,
+            max_rps,
+            timeout,
+            retries,
+            cache_dir,
+            lenient,
+            compression,
+        } = *options;
+        if max_rps.is_nan() || max_rps <= 0.0 {
+            bail!(
+                "The maximum requests per second must be positive, got {}",
+                max_rps
+            );
+        }
+        if timeout == Duration::from_secs(0) {
+            bail!("The timeout must be positive");
+        }
+
+        // gzip responses are decoded by reqwest, deflate responses by `inflate`
+        let mut builder = blocking::Client::builder()
+            .gzip(compression)
+            .connect_timeout(timeout)
+            .timeout(timeout);
+        if let Some(proxy) = proxy {
+            let proxy =
+                Proxy::all(proxy).with_context(|| format!("Invalid proxy url '{}'", proxy))?;
+            builder = builder.no_proxy().proxy(proxy);
+        }
+        let client = builder.build().context("Could not create http client")?;
+
+        Ok(DataSource {
+            url: url.to_string(),
+            proxy: proxy.map(String::from).or_else(|| env_proxy(url)),
+            timeout,
+            retries,
+            lenient,
+            compression,
+            client,
+            cache: cache_dir.map(|dir| Cache::new(dir.to_path_buf())),
+            prefetcher: Prefetcher::new(PREFETCH_CAPACITY),
+            rate_limiter: RateLimiter::new(max_rps),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            offline: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// When the cached data shown instead of the unreachable api was fetched, `None` while
+    /// the api is reachable.
+    pub fn offline_since(&self) -> Option<SystemTime> {
+        *self.offline.lock().unwrap()
+    }
+
+    /// The number of requests currently sent, including background fetches.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    fn send(&self, request: blocking::RequestBuilder, url: &str) -> Result<blocking::Response> {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        let response = self.send_with_retries(request);
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+
+        match self.proxy {
+            Some(ref proxy) => response
+                .with_context(|| format!("Could not reach url '{}' via proxy '{}'", url, proxy)),
+            None => response.with_context(|| format!("Could not reach url '{}'", url)),
+        }
+    }
+
+    /// Sends `request` and retries connection errors and `5xx` responses up to `retries` times,
+    /// waiting `RETRY_BACKOFF` before the first retry and twice as long before every further
+    /// one. Any other error and `4xx` responses fail at once.
+    fn send_with_retries(&self, request: blocking::RequestBuilder) -> Result<blocking::Response> {
+        let attempts = self.retries + 1;
+        let mut backoff = RETRY_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            let attempt_request = request.try_clone().context("Could not clone the request")?;
+            self.rate_limiter.acquire();
+            let error = match attempt_request.send() {
+                Ok(response) if response.status().is_server_error() => {
+                    anyhow::Error::new(StatusError(response.status()))
+                }
+                Ok(response) if response.status().is_client_error() => {
+                    return Err(StatusError(response.status()).into())
+                }
+                Ok(response) => return Ok(response),
+                Err(error) if error.is_timeout() => {
+                    let message = format!(
+                        "No response within {:?}, the api is unreachable or too slow",
+                        self.timeout
+                    );
+                    return Err(anyhow::Error::new(error).context(message));
+                }
+                Err(error) if error.is_connect() || error.is_request() => anyhow::Error::new(error)
+                    .context("The api is unreachable, check the network connection and retry"),
+                Err(error) => return Err(error.into()),
+            };
+
+            if attempt >= attempts {
+                return Err(error.context(format!("Failed after {} attempts", attempts)));
+            }
+            thread::sleep(backoff);
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+
+    /// GETs `url` as text. Responses are cached under `key`, responses carrying an
+    /// `ETag`/`Last-Modified` are revalidated on subsequent requests and a `304 Not Modified`
+    /// returns the cached body. If the api is unreachable (see `is_unreachable`) the cached body
+    /// is returned, other errors like a `4xx` response fail.
+    fn get_conditional(&self, url: &str, key: &str) -> Result<Entry> {
+        let cached = self.cache.as_ref().and_then(|cache| cache.get(key));
+
+        let encodings = if self.compression {
+            "gzip, deflate"
+        } else {
+            "identity"
+        };
+        let mut request = self.client.get(url).header(ACCEPT_ENCODING, encodings);
+        if let Some(ref entry) = cached {
+            if let Some(ref etag) = entry.validators.etag {
+                request = request.header(IF_NONE_MATCH, etag.as_str());
+            }
+            if let Some(ref last_modified) = entry.validators.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified.as_str());
+            }
+        }
+
+        let response = match self.send(request, url) {
+            Ok(response) => {
+                *self.offline.lock().unwrap() = None;
+                response
+            }
+            Err(error) => match cached {
+                Some(entry) if is_unreachable(&error) => {
+                    *self.offline.lock().unwrap() = entry.modified.or(Some(SystemTime::now()));
+                    return Ok(entry);
+                }
+                _ => return Err(error),
+            },
+        };
+        if response.status() == StatusCode::NOT_MODIFIED {
+            if let Some(entry) = cached {
+                return Ok(entry);
+            }
+        }
+
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+        };
+        let validators = Validators {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        };
+        let next = header(LINK).and_then(|link| next_link(&link));
+
+        let deflated = header(CONTENT_ENCODING)
+            .is_some_and(|encoding| encoding.eq_ignore_ascii_case("deflate"));
+        let body = if deflated {
+            response
+                .bytes()
+                .map_err(anyhow::Error::from)
+                .and_then(|bytes| inflate(&bytes))
+        } else {
+            response.text().map_err(anyhow::Error::from)
+        }
+        .with_context(|| format!("Could not read response from url '{}'", url))?;
+
+        let entry = Entry {
+            body,
+            validators,
+            next,
+            modified: None,
+        };
+        if let Some(ref cache) = self.cache {
+            // the cache is best effort, a failed write must not fail the request
+            let _ = cache.put(key, &entry);
+        }
+        Ok(entry)
+    }
+
+    /// Reads all infrastructure infos, following a paginated response (`Link: <url>;
+    /// rel="next"` header or `next` field) up to `MAX_PAGES` pages.
+    pub fn read_infrastructure_infos(&self) -> Result<Vec<InfrastrukturInfo>> {
+        self.read_infrastructure_infos_with_progress(|_| {})
+    }
+
+    /// See `read_infrastructure_infos`, `progress` is called with the number of every page
+    /// before it is read.
+    pub fn read_infrastructure_infos_with_progress<F>(
+        &self,
+        mut progress: F,
+    ) -> Result<Vec<InfrastrukturInfo>>
+    where
+        F: FnMut(usize),
+    {
+        let mut indices = Vec::new();
+        let mut url = self.url.clone();
+        for page in 1..=MAX_PAGES {
+            progress(page);
+            let key = match page {
+                1 => "infrastrukturen".to_string(),
+                n => format!("infrastrukturen-page-{}", n),
+            };
+            let entry = self.get_conditional(&url, &key).with_context(|| {
+                format!("Could not read infrastructure indices from url '{}'", url)
+            })?;
+            let next = match serde_json::from_str(&entry.body).with_context(|| {
+                format!("Could not parse infrastrukturen (json) from url '{}'", url)
+            })? {
+                InfrastrukturPage::All(infos) => {
+                    indices.extend(infos);
+                    entry.next
+                }
+                InfrastrukturPage::Page { items, next } => {
+                    indices.extend(items);
+                    entry.next.or(next)
+                }
+            };
+
+            match next {
+                Some(next) => {
+                    url = Url::parse(&url)
+                        .and_then(|base| base.join(&next))
+                        .with_context(|| format!("Invalid next page url '{}'", next))?
+                        .to_string()
+                }
+                None => {
+                    indices.sort_by_key(|k| k.id);
+                    return Ok(indices);
+                }
+            }
+        }
+        bail!(
+            "The infrastructure indices have more than {} pages, stopped at url '{}'",
+            MAX_PAGES,
+            url
+        )
+    }
+
+    /// Reads the station map `id`, a prefetched or still prefetching one is used instead of
+    /// fetching it again.
+    pub fn read_station_map(&self, id: u64) -> Result<StationMap> {
+        match self.prefetcher.get(id) {
+            Some(station_map) => Ok(station_map),
+            None => self.fetch_station_map(id),
+        }
+    }
+
+    /// Reads the station maps of `ids` merged into one, see `StationMap::merge`.
+    pub fn read_merged_station_map(&self, ids: &[u64]) -> Result<StationMap> {
+        let mut station_maps = ids
+            .iter()
+            .map(|&id| self.read_station_map(id))
+            .collect::<Result<Vec<StationMap>>>()?;
+        if station_maps.is_empty() {
+            bail!("No infrastructure given");
+        }
+        let first = station_maps.remove(0);
+        Ok(first.merge(station_maps))
+    }
+
+    /// Fetches the station maps for `ids` in the background, see `read_station_map`.
+    pub fn prefetch_station_maps(&self, ids: &[u64]) {
+        let source = self.clone();
+        self.prefetcher
+            .prefetch(ids, move |id| source.fetch_station_map(id));
+    }
+
+    /// Reads the station map `id` from the cache, or fetches it if it isn't cached.
+    fn fetch_station_map(&self, id: u64) -> Result<StationMap> {
+        let key = format!("infrastruktur-{}", id);
+        if let Some(ref cache) = self.cache {
+            if let Some(entry) = cache.get(&key) {
+                match read_station_map_from_str(&entry.body, self.lenient) {
+                    Ok(station_map) => return Ok(station_map),
+                    // a corrupt or partial file is downloaded again, without its validators
+                    Err(_) => cache.remove(&key),
+                }
+            }
+        }
+
+        let url = format!("{}/{}", self.url.trim_end_matches('/'), id);
+        let body = match self.get_conditional(&url, &key) {
+            Ok(entry) => entry.body,
+            Err(error) if StatusError::is(&error, StatusCode::NOT_FOUND) => {
+                bail!("There is no infrastructure with the id {}", id)
+            }
+            Err(error) => {
+                return Err(error)
+                    .with_context(|| format!("Could not read infrastructure from url '{}'", &url))
+            }
+        };
+        read_station_map_from_str(&body, self.lenient)
+            .with_context(|| format!("Could not parse infrastructure (json) from url '{}'", &url))
+    }
+}
+
+/// The progress of `search_station`.
+pub enum SearchProgress<'a> {
+    /// A page of the infrastructure list is read, see `read_infrastructure_infos_with_progress`.
+    Page(usize),
+    /// An infrastructure was fetched.
+    Infrastructure(&'a InfrastrukturInfo, &'a Result<StationMap>),
+}
+
+/// The stations matching `query` in all infrastructures (of the timetable `year`) with the id
+/// of their infrastructure. `query` matches a `ds100` exactly or is part of a station name,
+/// both ignoring case. `progress` is called for every page of the infrastructure list and after
+/// every fetched infrastructure, infrastructures that can't be fetched are skipped.
+pub fn search_station<F>(
+    source: &DataSource,
+    query: &str,
+    year: Option<u32>,
+    mut progress: F,
+) -> Result<Vec<(u64, Station)>>
+where
+    F: FnMut(SearchProgress),
+{
+    let query = fold(query.trim());
+    let infos = source
+        .read_infrastructure_infos_with_progress(|page| progress(SearchProgress::Page(page)))?;
+
+    let mut found = Vec::new();
+    for info in infos
+        .iter()
+        .filter(|info| year.map_or(true, |year| info.fahrplanjahr == year))
+    {
+        let station_map = source.read_station_map(info.id);
+        progress(SearchProgress::Infrastructure(info, &station_map));
+        if let Ok(station_map) = station_map {
+            found.extend(
+                station_map
+                    .stations
+                    .into_iter()
+                    .filter(|station| {
+                        fold(&station.ds100) == query || fold(&station.longname).contains(&query)
+                    })
+                    .map(|station| (info.id, station)),
+            );
+        }
+    }
+    Ok(found)
+}
+
+/// Parses a station map from the json of an infrastructure, as returned by the api, see
+/// `StationMap::from_infrastruktur` for `lenient`.
+pub fn read_station_map_from_str(json: &str, lenient: bool) -> Result<StationMap> {
+    let infrastruktur: Infrastruktur = serde_json::from_str(json)?;
+    StationMap::from_infrastruktur(infrastruktur, lenient)
+}
+
+/// Reads a station map from a file with the json of an infrastructure, e.g. a saved api
+/// response.
+pub fn read_station_map_from_file(path: &Path, lenient: bool) -> Result<StationMap> {
+    let json = fs::read_to_string(path).map_err(|error| match error.kind() {
+        io::ErrorKind::NotFound => anyhow!("File '{}' not found", path.display()),
+        _ => anyhow!(error).context(format!("Could not read file '{}'", path.display())),
+    })?;
+    let infrastruktur: Infrastruktur = serde_json::from_str(&json).map_err(|error| {
+        let problem = match error.classify() {
+            serde_json::error::Category::Data => "is not the json of an infrastructure",
+            _ => "contains invalid json",
+        };
+        anyhow!(error).context(format!("File '{}' {}", path.display(), problem))
+    })?;
+    StationMap::from_infrastruktur(infrastruktur, lenient)
+        .with_context(|| format!("Could not read the infrastructure in '{}'", path.display()))
+}
+
+/// Trims `name` and collapses runs of whitespace into one space.
+fn normalize_name(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Lowercases `s` and folds the German special characters, so `munchen` matches `München`.
+/// Search compares queries and candidates folded.
+pub fn fold(s: &str) -> String {
+    let mut folded = String::with_capacity(s.len());
+    for c in s.chars().flat_map(char::to_lowercase) {
+        match c {
+            'ä' => folded.push('a'),
+            'ö' => folded.push('o'),
+            'ü' => folded.push('u'),
+            'ß' => folded.push_str("ss"),
+            c => folded.push(c),
+        }
+    }
+    folded
+}
+
+/// Decodes a `Content-Encoding: deflate` body, which is zlib wrapped or (by some servers) raw.
+fn inflate(bytes: &[u8]) -> Result<String> {
+    let mut body = String::new();
+    if ZlibDecoder::new(bytes).read_to_string(&mut body).is_ok() {
+        return Ok(body);
+    }
+
+    body.clear();
+    DeflateDecoder::new(bytes)
+        .read_to_string(&mut body)
+        .context("Could not decode deflate response")?;
+    Ok(body)
+}
+
+/// The proxy reqwest picks up from the environment for `url`, used to name it in errors.
+fn env_proxy(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let host = url.host_str()?;
+
+    let no_proxy = env::var("NO_PROXY")
+        .or_else(|_| env::var("no_proxy"))
+        .unwrap_or_default();
+    let excluded = no_proxy
+        .split(',')
+        .map(|pattern| pattern.trim().trim_start_matches('.'))
+        .filter(|pattern| !pattern.is_empty())
+        .any(|pattern| {
+            pattern == "*" || host == pattern || host.ends_with(&format!(".{}", pattern))
+        });
+    if excluded {
+        return None;
+    }
+
+    let vars = if url.scheme() == "https" {
+        ["HTTPS_PROXY", "https_proxy"]
+    } else {
+        ["HTTP_PROXY", "http_proxy"]
+    };
+    vars.iter()
+        .filter_map(|var| env::var(var).ok())
+        .find(|proxy| !proxy.is_empty())
+}
+
+/// Safety cap for following the pages of the infrastructure indices.
+const MAX_PAGES: usize = 100;
+
+/// The infrastructure indices, either all at once or one page of them.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum InfrastrukturPage {
+    All(Vec<InfrastrukturInfo>),
+    Page {
+        #[serde(alias = "data", alias = "results", alias = "content")]
+        items: Vec<InfrastrukturInfo>,
+        #[serde(default)]
+        next: Option<String>,
+    },
+}
+
+/// The `rel="next"` url of a `Link` header like `<https://...?page=2>; rel="next"`.
+fn next_link(link: &str) -> Option<String> {
+    link.split(',').find_map(|part| {
+        let mut params = part.split(';');
+        let url = params.next()?.trim().strip_prefix('<')?.strip_suffix('>')?;
+        params
+            .any(|param| {
+                param.trim().strip_prefix("rel=").is_some_and(|rel| {
+                    rel.trim_matches('"')
+                        .split_whitespace()
+                        .any(|rel| rel == "next")
+                })
+            })
+            .then(|| url.to_string())
+    })
+}
+
+/// An entry of the infrastructure list, see `DataSource::read_infrastructure_infos`.
 #[derive(Deserialize, Debug)]
 pub struct InfrastrukturInfo {
     pub id: u64,
     pub anzeigename: String,
     pub fahrplanjahr: u32,
-    pub gueltig_von: String,
-    pub gueltig_bis: String,
+    pub gueltig_von: ApiDate,
+    pub gueltig_bis: ApiDate,
+}
+
+impl InfrastrukturInfo {
+    /// The newest timetable year first, within a year the latest `gueltig_von` first.
+    /// Infrastructures without a valid `gueltig_von` come last in their year.
+    pub fn cmp_recency(&self, other: &InfrastrukturInfo) -> cmp::Ordering {
+        other
+            .fahrplanjahr
+            .cmp(&self.fahrplanjahr)
+            .then(other.gueltig_von.date.cmp(&self.gueltig_von.date))
+            .then(self.id.cmp(&other.id))
+    }
+}
+
+/// A date of the api as it was sent, and parsed if it is valid.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(from = "String")]
+pub struct ApiDate {
+    pub text: String,
+    pub date: Option<NaiveDate>,
+}
+
+impl From<String> for ApiDate {
+    /// Parses `YYYY-MM-DD`, a time after the date (`2020-12-13T00:00:00`) is ignored.
+    fn from(text: String) -> Self {
+        let day = text.trim().split(['T', ' ']).next().unwrap_or("");
+        let date = NaiveDate::parse_from_str(day, "%Y-%m-%d").ok();
+        ApiDate { text, date }
+    }
 }
 
-pub fn read_station_map(base_url: &str, id: u64) -> Result<StationMap> {
-    let url = format!("{}/{}", base_url.trim_end_matches("/"), id);
-    blocking::get(&url)
-        .with_context(|| format!("Could not read infrastructure from url '{}'", &url))?
-        .json()
-        .with_context(|| format!("Could not parse infrastructure (json) from url '{}'", &url))
-        .and_then(|infrastruktur: Infrastruktur| infrastruktur.try_into())
+impl fmt::Display for ApiDate {
+    /// The date as `DD.MM.YYYY`, an invalid date as it was sent.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.date {
+            Some(date) => write!(f, "{}", date.format("%d.%m.%Y")),
+            None => f.write_str(&self.text),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Infrastruktur {
     pub id: u64,
     pub anzeigename: String,
+    #[serde(default)]
+    pub fahrplanjahr: Option<u32>,
     pub ordnungsrahmen: Ordnungsrahmen,
     // ...
 }
@@ -82,61 +1136,117 @@ impl TryInto<StationMap> for Infrastruktur {
     type Error = anyhow::Error;
 
     fn try_into(self) -> Result<StationMap> {
+        self.into_station_map(false)
+    }
+}
+
+impl Infrastruktur {
+    /// See `StationMap::from_infrastruktur`.
+    fn into_station_map(self, lenient: bool) -> Result<StationMap> {
         let betriebsstellen = self.ordnungsrahmen.betriebsstellen;
         let streckensegmente = self.ordnungsrahmen.streckensegmente;
 
-        // Stations
-        let stations: Vec<Station> = betriebsstellen
-            .into_iter()
-            .map(|bst| Station {
-                ds100: bst.ds100,
-                longname: bst.langname,
-                coord: (bst.x, bst.y),
-            })
-            .collect();
+        // Stations (stations without coordinates can't be placed on the map)
+        let mut warnings = Vec::new();
+        let mut unplaced: HashSet<String> = HashSet::new();
+        let mut stations: Vec<Station> = Vec::with_capacity(betriebsstellen.len());
+        for mut bst in betriebsstellen {
+            bst.ds100 = bst.ds100.trim().to_string();
+            bst.langname = normalize_name(&bst.langname);
+            match (bst.x, bst.y) {
+                (Some(x), Some(y)) => stations.push(Station {
+                    ds100: bst.ds100,
+                    longname: bst.langname,
+                    coord: (x, y),
+                }),
+                _ => {
+                    warnings.push(format!(
+                        "Station '{}' ({}) has no coordinates and is not shown",
+                        bst.ds100, bst.langname
+                    ));
+                    unplaced.insert(bst.ds100);
+                }
+            }
+        }
 
-        // Stations-Index-Map
-        let mut stations_index_map: HashMap<&str, Station> = HashMap::new();
+        // Stations-Index-Map (stations and segments keep the api order, nothing depends on the
+        // iteration order of the index)
+        let mut stations_index_map: BTreeMap<&str, Station> = BTreeMap::new();
+        let mut duplicates: BTreeMap<&str, usize> = BTreeMap::new();
         for station in &stations {
-            stations_index_map.insert(station.ds100.as_str(), station.clone());
+            match stations_index_map.get(station.ds100.as_str()) {
+                Some(_) => *duplicates.entry(station.ds100.as_str()).or_insert(1) += 1,
+                None => {
+                    stations_index_map.insert(station.ds100.as_str(), station.clone());
+                }
+            }
+        }
+        // segments can't tell same named stations apart, they use the first one
+        for (ds100, count) in duplicates {
+            warnings.push(format!(
+                "Station '{}' exists {} times, segments use the first one ({})",
+                ds100, count, stations_index_map[ds100].longname
+            ));
         }
 
         // Segments
         let mut segments = Vec::with_capacity(streckensegmente.len());
-        for streckensegment in streckensegmente {
-            let from = (*stations_index_map
-                .get(&streckensegment.von.as_ref())
-                .with_context(|| {
-                    format!(
-                        "Station '{}' for Segment '{}' not found",
-                        streckensegment.von,
-                        streckensegment.to_string()
-                    )
-                })?)
-            .clone();
-            let to = (*stations_index_map
-                .get(&streckensegment.bis.as_str())
-                .with_context(|| {
-                    format!(
-                        "Station '{}' for Segment '{}' not found",
-                        streckensegment.bis,
-                        streckensegment.to_string()
-                    )
-                })?)
-            .clone();
-            let routenumber = streckensegment.streckennummer;
+        for mut streckensegment in streckensegmente {
+            // endpoints have to match the trimmed ds100s of the stations
+            streckensegment.von = streckensegment.von.trim().to_string();
+            streckensegment.bis = streckensegment.bis.trim().to_string();
+
+            if let Some(ds100) = [&streckensegment.von, &streckensegment.bis]
+                .iter()
+                .find(|ds100| unplaced.contains(ds100.as_str()))
+            {
+                warnings.push(format!(
+                    "Segment '{}' is not shown, station '{}' has no coordinates",
+                    streckensegment, ds100
+                ));
+                continue;
+            }
+
+            let (from, to) = match (
+                stations_index_map.get(streckensegment.von.as_str()),
+                stations_index_map.get(streckensegment.bis.as_str()),
+            ) {
+                (Some(from), Some(to)) => (from.clone(), to.clone()),
+                (from, _) => {
+                    let missing = if from.is_none() {
+                        &streckensegment.von
+                    } else {
+                        &streckensegment.bis
+                    };
+                    if !lenient {
+                        bail!(
+                            "Station '{}' for Segment '{}' not found",
+                            missing,
+                            streckensegment
+                        );
+                    }
+                    warnings.push(format!(
+                        "Segment '{}' is not shown, station '{}' not found",
+                        streckensegment, missing
+                    ));
+                    continue;
+                }
+            };
             segments.push(Segment {
                 from,
                 to,
-                routenumber,
+                routenumber: streckensegment.streckennummer,
+                electrified: streckensegment.elektrifiziert,
             });
         }
 
         Ok(StationMap {
             id: self.id,
-            name: self.anzeigename,
+            name: normalize_name(&self.anzeigename),
+            year: self.fahrplanjahr,
             stations,
             segments,
+            warnings,
         })
     }
 }
@@ -150,8 +1260,8 @@ pub struct Ordnungsrahmen {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Betriebsstelle {
-    pub x: f64,
-    pub y: f64,
+    pub x: Option<f64>,
+    pub y: Option<f64>,
     pub ds100: String,
 
     #[serde(rename = "langname_stammdaten")]
@@ -164,11 +1274,457 @@ pub struct Streckensegment {
     pub von: String,
     pub bis: String,
     pub streckennummer: u32,
+    pub elektrifiziert: Option<bool>,
     // ...
 }
 
-impl Streckensegment {
-    pub fn to_string(&self) -> String {
-        format!("{}-{}-{}", self.von, self.streckennummer, self.bis)
+impl fmt::Display for Streckensegment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}-{}", self.von, self.streckennummer, self.bis)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+
+    const INFOS: &str = r#"[{"id": 1, "anzeigename": "Test", "fahrplanjahr": 2020,
+        "gueltig_von": "2019-12-15", "gueltig_bis": "2020-12-12"}]"#;
+
+    /// A local http server answering one request per response in `responses`, each a head
+    /// without `Content-Length` and a body. Returns its url and the heads of the requests.
+    fn serve(responses: Vec<(&'static str, Vec<u8>)>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/infrastrukturen", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = Arc::clone(&requests);
+        thread::spawn(move || {
+            for (head, body) in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                    let read = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                }
+                received
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8_lossy(&request).to_lowercase());
+                let head = format!(
+                    "{}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    head,
+                    body.len()
+                );
+                stream.write_all(head.as_bytes()).unwrap();
+                stream.write_all(&body).unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    /// An empty cache dir of its own for every test.
+    fn cache_dir(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("tie-test-{}-{}", std::process::id(), test));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn source(url: &str, cache_dir: Option<&Path>) -> DataSource {
+        DataSource::new(
+            url,
+            &SourceOptions {
+                proxy: None,
+                max_rps: 100.0,
+                timeout: Duration::from_secs(5),
+                retries: 0,
+                cache_dir,
+                lenient: false,
+                compression: true,
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn not_modified_returns_the_cached_body() {
+        let (url, requests) = serve(vec![
+            ("HTTP/1.1 200 OK\r\nETag: \"v1\"", INFOS.as_bytes().to_vec()),
+            ("HTTP/1.1 304 Not Modified\r\nETag: \"v1\"", Vec::new()),
+        ]);
+        let dir = cache_dir("not-modified");
+        let source = source(&url, Some(&dir));
+
+        let first = source.read_infrastructure_infos().unwrap();
+        let second = source.read_infrastructure_infos().unwrap();
+        assert_eq!(first.len(), 1);
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].anzeigename, "Test");
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(!requests[0].contains("if-none-match"));
+        assert!(requests[1].contains("if-none-match: \"v1\""));
+        assert_eq!(source.offline_since(), None);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn follows_the_next_link_to_the_second_page() {
+        let second = r#"[{"id": 2, "anzeigename": "Zwei", "fahrplanjahr": 2019,
+            "gueltig_von": "2018-12-09", "gueltig_bis": "2019-12-14"}]"#;
+        let (url, requests) = serve(vec![
+            (
+                "HTTP/1.1 200 OK\r\nLink: </infrastrukturen?page=2>; rel=\"next\"",
+                INFOS.as_bytes().to_vec(),
+            ),
+            ("HTTP/1.1 200 OK", second.as_bytes().to_vec()),
+        ]);
+        let source = source(&url, None);
+
+        let mut pages = Vec::new();
+        let infos = source
+            .read_infrastructure_infos_with_progress(|page| pages.push(page))
+            .unwrap();
+        let ids: Vec<u64> = infos.iter().map(|info| info.id).collect();
+        assert_eq!(ids, vec![1, 2]);
+        assert_eq!(pages, vec![1, 2]);
+        assert!(requests.lock().unwrap()[1].starts_with("get /infrastrukturen?page=2 "));
+    }
+
+    #[test]
+    fn decodes_a_gzip_body() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(INFOS.as_bytes()).unwrap();
+        let (url, requests) = serve(vec![(
+            "HTTP/1.1 200 OK\r\nContent-Encoding: gzip",
+            encoder.finish().unwrap(),
+        )]);
+        let source = source(&url, None);
+
+        let infos = source.read_infrastructure_infos().unwrap();
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].anzeigename, "Test");
+        assert!(requests.lock().unwrap()[0].contains("accept-encoding: gzip, deflate"));
+    }
+
+    #[test]
+    fn station_without_y_is_skipped() {
+        let json = r#"{"id": 1, "anzeigename": "Test", "ordnungsrahmen": {
+            "betriebsstellen": [
+                {"x": 10.0, "y": 50.0, "ds100": "AA", "langname_stammdaten": "Alpha"},
+                {"x": 11.0, "ds100": "BB", "langname_stammdaten": "Beta"},
+                {"x": 12.0, "y": 51.0, "ds100": "CC", "langname_stammdaten": "Gamma"}
+            ],
+            "streckensegmente": [
+                {"von": "AA", "bis": "CC", "streckennummer": 1, "elektrifiziert": true},
+                {"von": "AA", "bis": "BB", "streckennummer": 2, "elektrifiziert": null}
+            ]
+        }}"#;
+        let station_map = read_station_map_from_str(json, false).unwrap();
+
+        let stations: Vec<&str> = station_map
+            .stations
+            .iter()
+            .map(|s| s.ds100.as_str())
+            .collect();
+        assert_eq!(stations, vec!["AA", "CC"]);
+        assert_eq!(station_map.segments.len(), 1);
+        assert_eq!(station_map.segments[0].routenumber, 1);
+        assert_eq!(
+            station_map.warnings,
+            vec![
+                "Station 'BB' (Beta) has no coordinates and is not shown",
+                "Segment 'AA-2-BB' is not shown, station 'BB' has no coordinates",
+            ]
+        );
+    }
+
+    fn dated_info(id: u64, fahrplanjahr: u32, gueltig_von: &str) -> InfrastrukturInfo {
+        InfrastrukturInfo {
+            id,
+            anzeigename: format!("Infrastruktur {}", id),
+            fahrplanjahr,
+            gueltig_von: ApiDate::from(gueltig_von.to_string()),
+            gueltig_bis: ApiDate::from(String::new()),
+        }
+    }
+
+    #[test]
+    fn infos_sort_by_recency() {
+        let mut infos = [
+            dated_info(1, 2019, "2018-12-09"),
+            dated_info(2, 2020, ""),
+            dated_info(3, 2020, "2019-12-15"),
+            dated_info(4, 2021, "2020-12-13T00:00:00"),
+            dated_info(5, 2020, "2020-02-31"),
+            dated_info(6, 2020, "2020-02-29"),
+            dated_info(7, 2020, "2019-12-15"),
+        ];
+        infos.sort_by(InfrastrukturInfo::cmp_recency);
+        let ids: Vec<u64> = infos.iter().map(|info| info.id).collect();
+        // invalid and empty dates last in their year, equal dates by id
+        assert_eq!(ids, vec![4, 6, 3, 7, 2, 5, 1]);
+    }
+
+    #[test]
+    fn dates_are_validated() {
+        let date = |s: &str| ApiDate::from(s.to_string()).date;
+        assert_eq!(date("2020-02-29"), NaiveDate::from_ymd_opt(2020, 2, 29));
+        assert_eq!(
+            date("2020-12-13T00:00:00"),
+            NaiveDate::from_ymd_opt(2020, 12, 13)
+        );
+        assert!(date("2020-12-31").is_some());
+        assert!(date("2000-02-29").is_some());
+        assert!(date("2020-02-30").is_none());
+        assert!(date("2019-02-29").is_none());
+        assert!(date("1900-02-29").is_none());
+        assert!(date("2020-04-31").is_none());
+        assert!(date("2020-13-01").is_none());
+        assert!(date("2020-01-00").is_none());
+        assert!(date("").is_none());
+    }
+
+    #[test]
+    fn dates_are_displayed() {
+        assert_eq!(
+            ApiDate::from("2019-12-15".to_string()).to_string(),
+            "15.12.2019"
+        );
+        assert_eq!(
+            ApiDate::from("unbekannt".to_string()).to_string(),
+            "unbekannt"
+        );
+    }
+
+    #[test]
+    fn fold_ignores_case_and_umlauts() {
+        assert_eq!(fold("München"), "munchen");
+        assert!(fold("München Hbf").contains(&fold("munchen")));
+        assert_eq!(fold("STRASSE"), fold("straße"));
+        assert_eq!(fold("Ärger Öl Übel"), "arger ol ubel");
+        assert_eq!(fold("äöüß"), "aouss");
+        assert_eq!(fold("ÄÖÜ"), "aou");
+        assert_eq!(fold("Köln-Deutz"), "koln-deutz");
+    }
+
+    /// Stations and segments out of ds100 order, with a duplicate and an unknown station.
+    const UNORDERED: &str = r#"{"id": 7, "anzeigename": "Unordered", "ordnungsrahmen": {
+        "betriebsstellen": [
+            {"x": 3.0, "y": 3.0, "ds100": "ZZ", "langname_stammdaten": "Zulu"},
+            {"x": 1.0, "y": 1.0, "ds100": "MM", "langname_stammdaten": "Mike"},
+            {"x": 2.0, "y": 2.0, "ds100": "AA", "langname_stammdaten": "Alpha"},
+            {"x": 4.0, "y": 4.0, "ds100": "MM", "langname_stammdaten": "Mike 2"},
+            {"x": 5.0, "y": 5.0, "ds100": "KK", "langname_stammdaten": "Kilo"}
+        ],
+        "streckensegmente": [
+            {"von": "ZZ", "bis": "AA", "streckennummer": 3, "elektrifiziert": true},
+            {"von": "KK", "bis": "QQ", "streckennummer": 9, "elektrifiziert": null},
+            {"von": "MM", "bis": "ZZ", "streckennummer": 1, "elektrifiziert": false},
+            {"von": "AA", "bis": "KK", "streckennummer": 2, "elektrifiziert": null}
+        ]
+    }}"#;
+
+    /// The orderings of a station map: stations, segments and warnings.
+    fn orderings(station_map: &StationMap) -> (Vec<String>, Vec<String>, Vec<String>) {
+        (
+            station_map
+                .stations
+                .iter()
+                .map(|station| format!("{} {:?}", station.ds100, station.coord))
+                .collect(),
+            station_map
+                .segments
+                .iter()
+                .map(|segment| {
+                    format!(
+                        "{}-{}-{}",
+                        segment.from.ds100, segment.routenumber, segment.to.ds100
+                    )
+                })
+                .collect(),
+            station_map.warnings.clone(),
+        )
+    }
+
+    #[test]
+    fn conversions_have_identical_orderings() {
+        let first = read_station_map_from_str(UNORDERED, true).unwrap();
+        let second = read_station_map_from_str(UNORDERED, true).unwrap();
+        assert_eq!(orderings(&first), orderings(&second));
+
+        // the api order is kept, segments use the first of the duplicate stations
+        let (stations, segments, _) = orderings(&first);
+        assert_eq!(
+            stations,
+            vec![
+                "ZZ (3.0, 3.0)",
+                "MM (1.0, 1.0)",
+                "AA (2.0, 2.0)",
+                "MM (4.0, 4.0)",
+                "KK (5.0, 5.0)"
+            ]
+        );
+        assert_eq!(segments, vec!["ZZ-3-AA", "MM-1-ZZ", "AA-2-KK"]);
+        assert_eq!(first.segments[1].from.coord, (1.0, 1.0));
+    }
+
+    #[test]
+    fn names_and_ids_are_trimmed() {
+        let json = r#"{"id": 1, "anzeigename": "  Netz   2020 ", "ordnungsrahmen": {
+            "betriebsstellen": [
+                {"x": 1.0, "y": 1.0, "ds100": " AA ", "langname_stammdaten": " Alpha \t Nord  "},
+                {"x": 2.0, "y": 2.0, "ds100": "BB\t", "langname_stammdaten": "Beta"}
+            ],
+            "streckensegmente": [
+                {"von": "AA  ", "bis": " BB", "streckennummer": 1, "elektrifiziert": true}
+            ]
+        }}"#;
+        let station_map = read_station_map_from_str(json, false).unwrap();
+
+        assert_eq!(station_map.name, "Netz 2020");
+        assert_eq!(station_map.stations[0].ds100, "AA");
+        assert_eq!(station_map.stations[0].longname, "Alpha Nord");
+        assert_eq!(station_map.stations[1].ds100, "BB");
+        assert_eq!(station_map.segments.len(), 1);
+        assert_eq!(station_map.segments[0].from.ds100, "AA");
+        assert_eq!(station_map.segments[0].to.ds100, "BB");
+        assert!(station_map.warnings.is_empty());
+    }
+
+    fn station(ds100: &str, x: f64) -> Station {
+        Station {
+            ds100: ds100.to_string(),
+            longname: ds100.to_string(),
+            coord: (x, 0.0),
+        }
+    }
+
+    /// A map of the stations `ds100s` and the segments `(from, to)` between them.
+    fn network(ds100s: &[&str], segments: &[(&str, &str)]) -> StationMap {
+        let stations: Vec<Station> = ds100s
+            .iter()
+            .enumerate()
+            .map(|(index, ds100)| station(ds100, index as f64))
+            .collect();
+        let find = |ds100: &str| {
+            stations
+                .iter()
+                .find(|station| station.ds100 == ds100)
+                .cloned()
+                .unwrap_or_else(|| station(ds100, -1.0))
+        };
+        let segments = segments
+            .iter()
+            .map(|&(from, to)| Segment {
+                from: find(from),
+                to: find(to),
+                routenumber: 1,
+                electrified: None,
+            })
+            .collect();
+        StationMap {
+            id: 1,
+            name: "Netz".to_string(),
+            year: None,
+            stations,
+            segments,
+            warnings: Vec::new(),
+        }
+    }
+
+    fn neighbor_ds100s(station_map: &StationMap, ds100: &str, undirected: bool) -> Vec<String> {
+        station_map
+            .neighbors(ds100, undirected)
+            .iter()
+            .map(|station| station.ds100.clone())
+            .collect()
+    }
+
+    #[test]
+    fn one_way_segment_directed_and_undirected() {
+        // A and B are connected both ways, B -> C only one way, D has no segments
+        let station_map = network(&["A", "B", "C", "D"], &[("A", "B"), ("B", "A"), ("B", "C")]);
+
+        assert_eq!(neighbor_ds100s(&station_map, "B", true), vec!["A", "C"]);
+        assert_eq!(neighbor_ds100s(&station_map, "B", false), vec!["A", "C"]);
+        assert_eq!(neighbor_ds100s(&station_map, "C", true), vec!["B"]);
+        assert!(neighbor_ds100s(&station_map, "C", false).is_empty());
+        assert!(neighbor_ds100s(&station_map, "D", true).is_empty());
+
+        assert_eq!(station_map.components(true), vec![vec![0, 1, 2], vec![3]]);
+        assert_eq!(
+            station_map.components(false),
+            vec![vec![0, 1], vec![2], vec![3]]
+        );
+    }
+
+    #[test]
+    fn index_payloads_deserialize() {
+        let infos = match serde_json::from_str(INFOS).unwrap() {
+            InfrastrukturPage::All(infos) => infos,
+            page => panic!("expected all infos, got {:?}", page),
+        };
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].id, 1);
+        assert_eq!(infos[0].anzeigename, "Test");
+        assert_eq!(infos[0].fahrplanjahr, 2020);
+        assert_eq!(infos[0].gueltig_von.text, "2019-12-15");
+        assert_eq!(
+            infos[0].gueltig_von.date,
+            NaiveDate::from_ymd_opt(2019, 12, 15)
+        );
+        assert_eq!(infos[0].gueltig_bis.to_string(), "12.12.2020");
+
+        let page = format!(r#"{{"data": {}, "next": "?page=2"}}"#, INFOS);
+        match serde_json::from_str(&page).unwrap() {
+            InfrastrukturPage::Page { items, next } => {
+                assert_eq!(items.len(), 1);
+                assert_eq!(next.as_deref(), Some("?page=2"));
+            }
+            page => panic!("expected a page, got {:?}", page),
+        }
+
+        // an invalid date is kept as text
+        let info: InfrastrukturInfo = serde_json::from_str(
+            r#"{"id": 2, "anzeigename": "Zwei", "fahrplanjahr": 2019,
+                "gueltig_von": "unbekannt", "gueltig_bis": ""}"#,
+        )
+        .unwrap();
+        assert_eq!(info.gueltig_von.text, "unbekannt");
+        assert_eq!(info.gueltig_von.date, None);
+    }
+
+    #[test]
+    fn isolated_stations_have_no_segments() {
+        // E is only reached by a segment from a station that is not listed
+        let station_map = network(
+            &["A", "B", "C", "D", "E", "F"],
+            &[("A", "B"), ("C", "C"), ("X", "E")],
+        );
+        let isolated: Vec<&str> = station_map
+            .isolated_stations()
+            .iter()
+            .map(|station| station.ds100.as_str())
+            .collect();
+        assert_eq!(isolated, vec!["D", "F"]);
+        assert_eq!(station_map.stats().isolated, 2);
+
+        assert!(network(&[], &[]).isolated_stations().is_empty());
+    }
+
+    #[test]
+    fn extent_without_coordinates() {
+        let extent = calc_extent(&[]);
+        assert_eq!(
+            (extent.min_x, extent.max_x, extent.min_y, extent.max_y),
+            (0.0, 1.0, 0.0, 1.0)
+        );
     }
 }
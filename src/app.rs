@@ -0,0 +1,298 @@
+use anyhow::{Context, Result};
+use termion::event::{Key, MouseEvent};
+use tui::backend::Backend;
+use tui::widgets::Widget;
+use tui::Frame;
+
+use crate::api::DataSource;
+use crate::config::Config;
+use crate::history::History;
+use crate::keymap::Command;
+use crate::state::State;
+use crate::widgets::{Action, Ascii, CommandPalette, NoColor, PaletteInput, Screen, TermWidget};
+
+//
+// App
+//
+
+/// The state of the interactive application: the shown widget, the widgets to return to and
+/// everything shared between them.
+pub struct App {
+    /// Always set, only taken while a widget is replaced by the next one.
+    widget: Option<TermWidget>,
+    /// The widgets `Back` returns to, the last one first.
+    stack: Vec<TermWidget>,
+    source: DataSource,
+    config: Config,
+    palette: Option<CommandPalette>,
+    /// The scroll offset of the help overlay while it is open.
+    help: Option<u16>,
+    history: History,
+    state: State,
+    status: Option<String>,
+    /// The map coordinates under the mouse.
+    cursor: Option<String>,
+    /// Ticks since the spinner started, `None` while no request is in flight.
+    spinner: Option<usize>,
+    running: bool,
+}
+
+const SPINNER_FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+const ASCII_SPINNER_FRAMES: [char; 4] = ['|', '/', '-', '\\'];
+
+/// Ticks every spinner frame is shown for.
+const SPINNER_TICKS: usize = 2;
+
+impl App {
+    pub fn new(
+        source: DataSource,
+        mut config: Config,
+        action: Action,
+        state: State,
+    ) -> Result<Self> {
+        config.recent_infrastructures = state.recent_infrastructures().to_vec();
+        let widget = action.run(&source, &config)?;
+        Ok(App {
+            widget: Some(widget),
+            stack: Vec::new(),
+            source,
+            config,
+            palette: None,
+            help: None,
+            history: History::load(History::default_path()),
+            state,
+            status: None,
+            cursor: None,
+            spinner: None,
+            running: true,
+        })
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    pub fn handle_key(&mut self, key: Key) {
+        self.status = None;
+        // any key skips an animation
+        if self.widget_mut().finish_animation() {
+            return;
+        }
+
+        if let Some(scroll) = self.help {
+            // the help is modal, it only scrolls and closes
+            self.help = match (key, self.config.keys.command(key)) {
+                (Key::Esc, _) | (_, Some(Command::Back)) | (_, Some(Command::ToggleHelp)) => None,
+                (_, Some(Command::Up)) => Some(scroll.saturating_sub(1)),
+                (_, Some(Command::Down)) => Some((scroll + 1).min(Command::ALL.len() as u16 - 1)),
+                _ => Some(scroll),
+            };
+            return;
+        }
+
+        let command = match self.palette {
+            Some(ref mut palette) => match palette.select_key(key, &mut self.history) {
+                PaletteInput::Editing => None,
+                PaletteInput::Cancel => {
+                    self.palette = None;
+                    None
+                }
+                PaletteInput::Execute(command) => {
+                    self.palette = None;
+                    // the history is best effort, a failed write must not stop tie
+                    let _ = self.history.save();
+                    Some(command)
+                }
+            },
+            None => {
+                let widget = self.widget.as_mut().expect("widget is set");
+                if widget.select_key(key, &self.source, &mut self.history) {
+                    if key == Key::Char('\n') {
+                        // `Enter` recorded the query of the prompt. The history is best effort, a
+                        // failed write must not stop tie
+                        let _ = self.history.save();
+                    }
+                    None
+                } else {
+                    self.config.keys.command(key)
+                }
+            }
+        };
+
+        if let Some(command) = command {
+            self.handle_command(command);
+        }
+    }
+
+    pub fn handle_mouse(&mut self, event: MouseEvent) {
+        self.widget_mut().finish_animation();
+        let listed = self.listed_infrastructure();
+        let widget = self.widget.take().expect("widget is set");
+        let widget = widget.select_mouse(event, &self.source, &self.config, &mut self.stack);
+        self.widget = Some(widget);
+        self.remember_opened(listed);
+
+        let (x, y) = match event {
+            MouseEvent::Press(_, x, y) | MouseEvent::Release(x, y) | MouseEvent::Hold(x, y) => {
+                (x, y)
+            }
+        };
+        self.handle_motion(x, y);
+    }
+
+    /// Updates the map coordinates under the mouse, returns whether they changed.
+    pub fn handle_motion(&mut self, x: u16, y: u16) -> bool {
+        let cursor = self.widget_mut().cursor_position(x, y);
+        let changed = cursor != self.cursor;
+        self.cursor = cursor;
+        changed
+    }
+
+    pub fn handle_paste(&mut self, text: &str) {
+        match self.palette {
+            Some(ref mut palette) => palette.paste(text),
+            None => self.widget_mut().paste(text),
+        }
+    }
+
+    /// Advances running animations and the spinner, returns whether the screen has to be
+    /// redrawn.
+    pub fn tick(&mut self) -> bool {
+        let loaded = self.poll_loading();
+        let spinner = self.spinner;
+        self.spinner = match self.source.in_flight() {
+            0 => None,
+            _ => Some(spinner.map_or(0, |ticks| ticks + 1)),
+        };
+        let spinner_changed = match (spinner, self.spinner) {
+            (None, None) => false,
+            (Some(before), Some(after)) => before / SPINNER_TICKS != after / SPINNER_TICKS,
+            _ => true,
+        };
+        self.widget_mut().tick() || spinner_changed || loaded
+    }
+
+    /// Shows the result of a finished background action, returns whether there was one.
+    fn poll_loading(&mut self) -> bool {
+        if !matches!(self.widget, Some(TermWidget::Loading(_))) {
+            return false;
+        }
+        let listed = self.listed_infrastructure();
+        let widget = self.widget.take().expect("widget is set");
+        self.widget = Some(widget.poll(&mut self.stack));
+        self.remember_opened(listed);
+        !matches!(self.widget, Some(TermWidget::Loading(_)))
+    }
+
+    pub fn draw<B: Backend>(&mut self, f: &mut Frame<B>) {
+        let area = f.size();
+        let frames: &[char] = if self.config.ascii {
+            &ASCII_SPINNER_FRAMES
+        } else {
+            &SPINNER_FRAMES
+        };
+        let spinner = self
+            .spinner
+            .map(|ticks| frames[ticks / SPINNER_TICKS % frames.len()]);
+        let mut screen = Screen {
+            widget: self.widget.as_mut().expect("widget is set"),
+            palette: self.palette.as_mut(),
+            offline_since: self.source.offline_since(),
+            status: self.status.as_deref(),
+            spinner,
+            cursor: self.cursor.as_deref(),
+            keys: &self.config.keys,
+            help: self.help,
+            theme: &self.config.theme,
+        };
+        match (self.config.ascii, self.config.no_color) {
+            (false, false) => screen.render(f, area),
+            (true, false) => Ascii(&mut screen).render(f, area),
+            (false, true) => NoColor(&mut screen).render(f, area),
+            (true, true) => NoColor(&mut Ascii(&mut screen)).render(f, area),
+        }
+    }
+
+    fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::Quit => self.running = false,
+            Command::ToggleHighlight => {
+                self.config.highlight = self.config.highlight.next();
+                // widgets on the stack get the new config too, they are shown again on `Back`
+                let config = &self.config;
+                self.widget
+                    .iter_mut()
+                    .chain(self.stack.iter_mut())
+                    .for_each(|widget| widget.set_config(config));
+            }
+            Command::OpenPalette => self.palette = Some(CommandPalette::new(&self.config)),
+            Command::ToggleHelp => self.help = Some(0),
+            Command::OpenWebPage if !matches!(self.widget, Some(TermWidget::Error(_))) => {
+                if let Err(error) = self.open_web_page() {
+                    self.status = Some(format!("{:#}", error));
+                }
+            }
+            Command::ShowView => {
+                if let Some(view) = self.widget.as_ref().and_then(TermWidget::view) {
+                    self.status = Some(format!("--bbox={}", view));
+                }
+            }
+            command => {
+                let listed = self.listed_infrastructure();
+                let widget = self.widget.take().expect("widget is set");
+                let widget =
+                    widget.select_command(command, &self.source, &self.config, &mut self.stack);
+                self.widget = Some(widget);
+                self.remember_opened(listed);
+            }
+        }
+    }
+
+    fn open_web_page(&self) -> Result<()> {
+        let id = self
+            .widget
+            .as_ref()
+            .and_then(TermWidget::infrastructure_id)
+            .context("No infrastructure selected")?;
+        let url = self.config.web_url(id);
+        open::that(&url).with_context(|| format!("Could not open '{}' in a browser", url))
+    }
+
+    /// The infrastructure selected in the infrastructure list, if the list is shown.
+    fn listed_infrastructure(&self) -> Option<u64> {
+        let widget = match self.widget {
+            // a map opened from the list loads on top of it
+            Some(TermWidget::Loading(ref widget)) => widget.previous(),
+            ref widget => widget.as_ref()?,
+        };
+        match *widget {
+            TermWidget::InfrastrukturSelection(_) => widget.infrastructure_id(),
+            _ => None,
+        }
+    }
+
+    /// Remembers the infrastructure `listed` before the widget changed as recent if its map is
+    /// shown now.
+    fn remember_opened(&mut self, listed: Option<u64>) {
+        let opened = match self.widget {
+            Some(ref widget @ TermWidget::Map(_)) => widget.infrastructure_id(),
+            _ => None,
+        };
+        if let Some(id) = listed.filter(|&id| opened == Some(id)) {
+            self.state.push_recent_infrastructure(id);
+            self.config.recent_infrastructures = self.state.recent_infrastructures().to_vec();
+            // the list on the stack shows the new recent ones after `Back`
+            let config = &self.config;
+            self.stack
+                .iter_mut()
+                .for_each(|widget| widget.set_config(config));
+            // the state is best effort, a failed write must not stop tie
+            let _ = self.state.save();
+        }
+    }
+
+    fn widget_mut(&mut self) -> &mut TermWidget {
+        self.widget.as_mut().expect("widget is set")
+    }
+}
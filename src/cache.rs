@@ -0,0 +1,113 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+//
+// Cache
+//
+
+/// On-disk cache for raw api responses. Every entry consists of the body (`<key>.json`) and
+/// the validators needed for conditional requests plus the next page link (`<key>.meta.json`).
+/// Without a network the cached bodies are used as they are.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    dir: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub body: String,
+    pub validators: Validators,
+    /// The url of the next page of a paginated response.
+    pub next: Option<String>,
+    /// When the entry was written, unknown for entries that aren't read from the cache.
+    pub modified: Option<SystemTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// The content of `<key>.meta.json`, flattened so meta files without `next` still parse.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct Meta {
+    #[serde(flatten)]
+    validators: Validators,
+    #[serde(default)]
+    next: Option<String>,
+}
+
+impl Cache {
+    pub fn new(dir: PathBuf) -> Self {
+        Cache { dir }
+    }
+
+    /// `<os cache dir>/tie`, if the os has a cache dir.
+    pub fn default_dir() -> Option<PathBuf> {
+        dirs::cache_dir().map(|dir| dir.join("tie"))
+    }
+
+    pub fn get(&self, key: &str) -> Option<Entry> {
+        let body_path = self.body_path(key);
+        let body = fs::read_to_string(&body_path).ok()?;
+        let modified = fs::metadata(&body_path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        let meta: Meta = fs::read_to_string(self.meta_path(key))
+            .ok()
+            .and_then(|meta| serde_json::from_str(&meta).ok())
+            .unwrap_or_default();
+        Some(Entry {
+            body,
+            validators: meta.validators,
+            next: meta.next,
+            modified,
+        })
+    }
+
+    pub fn put(&self, key: &str, entry: &Entry) -> Result<()> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Could not create cache dir '{}'", self.dir.display()))?;
+
+        write_atomic(&self.body_path(key), &entry.body)?;
+        let meta = Meta {
+            validators: entry.validators.clone(),
+            next: entry.next.clone(),
+        };
+        write_atomic(&self.meta_path(key), &serde_json::to_string(&meta)?)
+    }
+
+    /// Removes the entry `key`, a missing entry is no error.
+    pub fn remove(&self, key: &str) {
+        let _ = fs::remove_file(self.body_path(key));
+        let _ = fs::remove_file(self.meta_path(key));
+    }
+
+    fn body_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    fn meta_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.meta.json", key))
+    }
+}
+
+/// Writes to a temporary file first, so concurrent readers never see a partial file.
+fn write_atomic(path: &Path, contents: &str) -> Result<()> {
+    static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let tmp_path = path.with_extension(format!(
+        "tmp.{}.{}",
+        process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::write(&tmp_path, contents)
+        .and_then(|_| fs::rename(&tmp_path, path))
+        .with_context(|| format!("Could not write cache file '{}'", path.display()))
+}
@@ -0,0 +1,278 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Deserializer};
+use tui::style::{Color, Modifier, Style};
+
+use crate::api::Extent;
+use crate::keymap::KeyMap;
+
+//
+// Config
+//
+
+const DEFAULT_TICK_MS: u64 = 40;
+
+const DEFAULT_WEB_URL: &str = "https://www.trassenfinder.de/?infrastruktur={id}";
+
+/// Settings read from `config.toml`, every setting is optional.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Config {
+    pub highlight: HighlightStyle,
+    /// Draw borders and the map with ASCII characters only.
+    pub ascii: bool,
+    /// Draw without colors and text styles, the selected list item is marked with `>`.
+    pub no_color: bool,
+    /// Moving past the end of a list continues at its other end.
+    pub wrap: bool,
+    /// Zoom into a map after opening it.
+    pub animate: bool,
+    /// Milliseconds between the ticks driving animations and the spinner.
+    pub tick_ms: Option<u64>,
+    /// Grid size in terminal cells stations are clustered on when the map shows more stations
+    /// than it has cells, defaults to a size derived from the map size.
+    pub cluster_cells: Option<u16>,
+    /// Draw larger y coordinates further down.
+    pub flip_y: bool,
+    /// Follow segments only in their direction, the network is undirected by default.
+    pub directed: bool,
+    /// Draw the direction of every segment, not only of the selected one.
+    pub arrows: bool,
+    /// Leave out segments with unknown stations instead of failing to open the map.
+    pub lenient: bool,
+    /// Url template of the web page of an infrastructure, `{id}` is replaced by its id.
+    pub web_url: Option<String>,
+    /// The panel arrow keys apply to when a map opens.
+    pub default_panel: Panel,
+    pub keys: KeyMap,
+    pub theme: Theme,
+    /// Route file given with `--route`, not read from the config file.
+    #[serde(skip)]
+    pub route_file: Option<PathBuf>,
+    /// Timetable year given with `--year`, not read from the config file.
+    #[serde(skip)]
+    pub year: Option<u32>,
+    /// The infrastructures last opened from the list, the most recent first. Remembered from
+    /// the last runs, not read from the config file.
+    #[serde(skip)]
+    pub recent_infrastructures: Vec<u64>,
+    /// Bounding box given with `--bbox`, not read from the config file.
+    #[serde(skip)]
+    pub bbox: Option<Extent>,
+}
+
+impl Config {
+    /// Loads the config from `path`, or from the default path if it exists.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match Self::default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Config::default()),
+            },
+        };
+
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Could not read config file '{}'", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("Could not parse config file '{}'", path.display()))
+    }
+
+    /// The interval of the tick events, `tick_ms` or 40 ms.
+    pub fn tick_rate(&self) -> Duration {
+        Duration::from_millis(self.tick_ms.unwrap_or(DEFAULT_TICK_MS).max(1))
+    }
+
+    /// The style of the selected list item, `highlight` in the `theme.highlight` color.
+    pub fn highlight_style(&self) -> Style {
+        let style = self.highlight.style();
+        match (self.theme.highlight, self.highlight) {
+            (None, _) => style,
+            (Some(color), HighlightStyle::Background) => style.bg(color),
+            (Some(color), _) => style.fg(color),
+        }
+    }
+
+    /// The symbol in front of the selected list item, needed without colors.
+    pub fn highlight_symbol(&self) -> &'static str {
+        if self.no_color {
+            ">"
+        } else {
+            ""
+        }
+    }
+
+    /// The url of the web page of the infrastructure `id`.
+    pub fn web_url(&self, id: u64) -> String {
+        self.web_url
+            .as_deref()
+            .unwrap_or(DEFAULT_WEB_URL)
+            .replace("{id}", &id.to_string())
+    }
+
+    /// `<os config dir>/tie/config.toml`, if the os has a config dir.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("tie").join("config.toml"))
+    }
+}
+
+/// A panel of the map screen.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Panel {
+    #[default]
+    Stations,
+    Segments,
+    Map,
+}
+
+/// How the selected item of a list is highlighted.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum HighlightStyle {
+    #[default]
+    Bold,
+    Reversed,
+    Background,
+}
+
+impl HighlightStyle {
+    pub fn next(self) -> Self {
+        match self {
+            HighlightStyle::Bold => HighlightStyle::Reversed,
+            HighlightStyle::Reversed => HighlightStyle::Background,
+            HighlightStyle::Background => HighlightStyle::Bold,
+        }
+    }
+
+    pub fn style(self) -> Style {
+        match self {
+            HighlightStyle::Bold => Style::default().modifier(Modifier::BOLD),
+            HighlightStyle::Reversed => Style::default().modifier(Modifier::REVERSED),
+            HighlightStyle::Background => Style::default().fg(Color::Black).bg(Color::White),
+        }
+    }
+}
+
+//
+// Theme
+//
+
+/// The colors of the map, the lists and the status line. Colors are names like `blue` or
+/// `lightred`, `#rrggbb` or a color index of a 256 color terminal.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Theme {
+    #[serde(deserialize_with = "deserialize_color")]
+    pub station: Color,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub selected_station: Color,
+    /// Stations without segments.
+    #[serde(deserialize_with = "deserialize_color")]
+    pub isolated_station: Color,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub label: Color,
+    /// Segments of unknown electrification.
+    #[serde(deserialize_with = "deserialize_color")]
+    pub segment: Color,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub electrified_segment: Color,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub not_electrified_segment: Color,
+    /// The segments starting or ending at the selected station.
+    #[serde(deserialize_with = "deserialize_color")]
+    pub incident_segment: Color,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub selected_segment: Color,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub route: Color,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub bookmark: Color,
+    /// The text of the lists.
+    #[serde(deserialize_with = "deserialize_color")]
+    pub list: Color,
+    /// The border of the panel the arrow keys apply to.
+    #[serde(deserialize_with = "deserialize_color")]
+    pub focus: Color,
+    /// The bars of the route histogram.
+    #[serde(deserialize_with = "deserialize_color")]
+    pub histogram: Color,
+    /// Status messages, the coordinates under the mouse and the spinner.
+    #[serde(deserialize_with = "deserialize_color")]
+    pub status: Color,
+    /// The color of the selected list item, the `highlight` style decides where it applies.
+    #[serde(deserialize_with = "deserialize_optional_color")]
+    pub highlight: Option<Color>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            station: Color::Blue,
+            selected_station: Color::Red,
+            isolated_station: Color::LightRed,
+            label: Color::White,
+            segment: Color::DarkGray,
+            electrified_segment: Color::Cyan,
+            not_electrified_segment: Color::Gray,
+            incident_segment: Color::Green,
+            selected_segment: Color::Yellow,
+            route: Color::Magenta,
+            bookmark: Color::LightYellow,
+            list: Color::White,
+            focus: Color::LightGreen,
+            histogram: Color::Cyan,
+            status: Color::Yellow,
+            highlight: None,
+        }
+    }
+}
+
+fn deserialize_color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    parse_color(&name).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_optional_color<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Color>, D::Error> {
+    deserialize_color(deserializer).map(Some)
+}
+
+fn parse_color(name: &str) -> Result<Color> {
+    let color = match name.to_lowercase().as_str() {
+        "reset" => Color::Reset,
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "gray" => Color::Gray,
+        "darkgray" => Color::DarkGray,
+        "lightred" => Color::LightRed,
+        "lightgreen" => Color::LightGreen,
+        "lightyellow" => Color::LightYellow,
+        "lightblue" => Color::LightBlue,
+        "lightmagenta" => Color::LightMagenta,
+        "lightcyan" => Color::LightCyan,
+        "white" => Color::White,
+        name => {
+            if let Some(hex) = name.strip_prefix('#').filter(|hex| hex.len() == 6) {
+                let rgb = u32::from_str_radix(hex, 16)
+                    .with_context(|| format!("Unknown color '{}'", name))?;
+                Color::Rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+            } else {
+                Color::Indexed(
+                    name.parse()
+                        .with_context(|| format!("Unknown color '{}'", name))?,
+                )
+            }
+        }
+    };
+    Ok(color)
+}
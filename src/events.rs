@@ -1,14 +1,26 @@
-use std::io;
+use std::io::{self, Read, Write};
 use std::sync::mpsc;
 use std::thread;
+use std::time::Duration;
 
-use termion::event::Key;
+use termion::event::{self as termion_event, Key, MouseEvent};
 use termion::input::TermRead;
 
 // https://github.com/fdehau/tui-rs/blob/master/examples/util/event.rs
 
+/// How often the terminal size is checked.
+const RESIZE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 pub enum Event<I> {
     Input(I),
+    Mouse(MouseEvent),
+    /// The mouse moved to the one-based position `x`, `y` without a pressed button.
+    Motion(u16, u16),
+    /// Text pasted with bracketed paste, without control characters.
+    Paste(String),
+    /// The terminal size changed, the screen has to be cleared and redrawn.
+    Resize,
+    Tick,
 }
 
 pub struct Events {
@@ -16,20 +28,69 @@ pub struct Events {
 }
 
 impl Events {
-    pub fn new() -> Events {
+    /// Reads input events, sends a `Tick` every `tick_rate` and a `Resize` when the terminal
+    /// size changed.
+    pub fn new(tick_rate: Duration) -> Events {
         let (tx, rx) = mpsc::channel();
 
+        let input_tx = tx.clone();
+        thread::spawn(move || {
+            let stdin = PasteMarkers::new(io::stdin());
+            let mut paste: Option<String> = None;
+            for event in stdin.events().flatten() {
+                let event = match (event, paste.as_mut()) {
+                    (termion_event::Event::Key(Key::Char(PASTE_START)), _) => {
+                        paste = Some(String::new());
+                        continue;
+                    }
+                    (termion_event::Event::Key(Key::Char(PASTE_END)), Some(_)) => {
+                        Event::Paste(paste.take().unwrap_or_default())
+                    }
+                    (termion_event::Event::Key(Key::Char(c)), Some(text)) => {
+                        if !c.is_control() {
+                            text.push(c);
+                        }
+                        continue;
+                    }
+                    (_, Some(_)) => continue,
+                    (termion_event::Event::Key(key), None) => Event::Input(key),
+                    (termion_event::Event::Mouse(mouse), None) => Event::Mouse(mouse),
+                    (termion_event::Event::Unsupported(bytes), None) => {
+                        match parse_motion(&bytes) {
+                            Some((x, y)) => Event::Motion(x, y),
+                            None => continue,
+                        }
+                    }
+                };
+                if input_tx.send(event).is_err() {
+                    return;
+                }
+            }
+        });
+
+        // termion has no SIGWINCH handling, the size is polled instead
+        let resize_tx = tx.clone();
         thread::spawn(move || {
-            let stdin = io::stdin();
-            for evt in stdin.keys() {
-                if let Ok(key) = evt {
-                    if let Err(_) = tx.send(Event::Input(key)) {
+            let mut size = termion::terminal_size().ok();
+            loop {
+                thread::sleep(RESIZE_POLL_INTERVAL);
+                let new_size = termion::terminal_size().ok();
+                if new_size != size {
+                    size = new_size;
+                    if resize_tx.send(Event::Resize).is_err() {
                         return;
                     }
                 }
             }
         });
 
+        thread::spawn(move || loop {
+            if tx.send(Event::Tick).is_err() {
+                return;
+            }
+            thread::sleep(tick_rate);
+        });
+
         Events { rx }
     }
 
@@ -37,3 +98,124 @@ impl Events {
         self.rx.recv()
     }
 }
+
+/// Parses the SGR report of a mouse motion without a pressed button, `ESC [ < 35 ; x ; y M`,
+/// which termion doesn't support.
+fn parse_motion(bytes: &[u8]) -> Option<(u16, u16)> {
+    let report = std::str::from_utf8(bytes).ok()?;
+    let position = report.strip_prefix("\x1b[<35;")?.strip_suffix('M')?;
+    let mut parts = position.split(';');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(x), Some(y), None) => Some((x.parse().ok()?, y.parse().ok()?)),
+        _ => None,
+    }
+}
+
+//
+// Terminal modes
+//
+
+/// Reports bracketed paste, see `PasteMarkers`.
+pub const BRACKETED_PASTE: u16 = 2004;
+
+/// Reports every mouse motion, termion's `MouseTerminal` only reports motions with a pressed
+/// button.
+pub const ANY_MOTION: u16 = 1003;
+
+// termion can't parse the bracketed paste sequences, `PasteMarkers` replaces them with chars
+// from the unicode private use area.
+const PASTE_START: char = '\u{e000}';
+const PASTE_END: char = '\u{e001}';
+
+/// Enables private terminal modes like `BRACKETED_PASTE` for the wrapped terminal and disables
+/// them again when dropped, like termion's `MouseTerminal`.
+pub struct ModesTerminal<W: Write> {
+    term: W,
+    modes: &'static [u16],
+}
+
+impl<W: Write> ModesTerminal<W> {
+    pub fn new(mut term: W, modes: &'static [u16]) -> Self {
+        // a terminal without a mode ignores its sequence
+        for mode in modes {
+            let _ = write!(term, "\x1b[?{}h", mode);
+        }
+        ModesTerminal { term, modes }
+    }
+}
+
+impl<W: Write> Drop for ModesTerminal<W> {
+    fn drop(&mut self) {
+        for mode in self.modes.iter().rev() {
+            let _ = write!(self.term, "\x1b[?{}l", mode);
+        }
+        let _ = self.term.flush();
+    }
+}
+
+impl<W: Write> Write for ModesTerminal<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.term.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.term.flush()
+    }
+}
+
+/// Replaces the bracketed paste sequences with `PASTE_START` and `PASTE_END`. A sequence split
+/// across two reads of the source is not recognized.
+struct PasteMarkers<R: Read> {
+    source: R,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: Read> PasteMarkers<R> {
+    fn new(source: R) -> Self {
+        PasteMarkers {
+            source,
+            buf: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    fn fill(&mut self) -> io::Result<usize> {
+        let mut chunk = [0u8; 1024];
+        let n = self.source.read(&mut chunk)?;
+
+        let (mut start, mut end) = ([0u8; 4], [0u8; 4]);
+        let start = PASTE_START.encode_utf8(&mut start).as_bytes();
+        let end = PASTE_END.encode_utf8(&mut end).as_bytes();
+
+        self.buf.clear();
+        self.pos = 0;
+        let mut rest = &chunk[..n];
+        while !rest.is_empty() {
+            if rest.starts_with(b"\x1b[200~") {
+                self.buf.extend_from_slice(start);
+                rest = &rest[6..];
+            } else if rest.starts_with(b"\x1b[201~") {
+                self.buf.extend_from_slice(end);
+                rest = &rest[6..];
+            } else {
+                self.buf.push(rest[0]);
+                rest = &rest[1..];
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl<R: Read> Read for PasteMarkers<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.buf.len() && self.fill()? == 0 {
+            return Ok(0);
+        }
+
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
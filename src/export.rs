@@ -0,0 +1,444 @@
+use std::collections::HashSet;
+use std::io::Write;
+
+use anyhow::Result;
+use serde_json::json;
+
+use crate::api::{calc_extent, StationMap};
+
+//
+// Distance matrix
+//
+
+/// Writes the distance matrix of `station_map` as CSV with the `ds100`s as headers.
+pub fn write_distance_matrix<W: Write>(writer: &mut W, station_map: &StationMap) -> Result<()> {
+    write!(writer, "ds100")?;
+    for station in &station_map.stations {
+        write!(writer, ",{}", csv_field(&station.ds100))?;
+    }
+    writeln!(writer)?;
+
+    for (station, distances) in station_map
+        .stations
+        .iter()
+        .zip(station_map.distance_matrix())
+    {
+        write!(writer, "{}", csv_field(&station.ds100))?;
+        for distance in distances {
+            write!(writer, ",{:.3}", distance)?;
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+//
+// Stations and segments
+//
+
+/// Writes the stations of `station_map` as CSV, `ds100,longname,x,y`.
+pub fn write_stations_csv<W: Write + ?Sized>(
+    writer: &mut W,
+    station_map: &StationMap,
+) -> Result<()> {
+    writeln!(writer, "ds100,longname,x,y")?;
+    for station in &station_map.stations {
+        writeln!(
+            writer,
+            "{},{},{},{}",
+            csv_field(&station.ds100),
+            csv_field(&station.longname),
+            station.coord.0,
+            station.coord.1
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes the segments of `station_map` as CSV, `routenumber,from_ds100,to_ds100`.
+pub fn write_segments_csv<W: Write + ?Sized>(
+    writer: &mut W,
+    station_map: &StationMap,
+) -> Result<()> {
+    writeln!(writer, "routenumber,from_ds100,to_ds100")?;
+    for segment in &station_map.segments {
+        writeln!(
+            writer,
+            "{},{},{}",
+            segment.routenumber,
+            csv_field(&segment.from.ds100),
+            csv_field(&segment.to.ds100)
+        )?;
+    }
+    Ok(())
+}
+
+//
+// GeoJSON
+//
+
+impl StationMap {
+    /// The GeoJSON of `write_geojson` as a string.
+    pub fn to_geojson(&self) -> String {
+        to_string(|writer| write_geojson(writer, self))
+    }
+}
+
+/// Writes `station_map` as a GeoJSON `FeatureCollection`, a `Point` per station and a
+/// `LineString` per segment. The coordinates are written as they are, GeoJSON readers expect
+/// them as `[x, y]`, i.e. `[lon, lat]`.
+pub fn write_geojson<W: Write + ?Sized>(writer: &mut W, station_map: &StationMap) -> Result<()> {
+    let stations = station_map.stations.iter().map(|station| {
+        json!({
+            "type": "Feature",
+            "geometry": {
+                "type": "Point",
+                "coordinates": [station.coord.0, station.coord.1],
+            },
+            "properties": {
+                "ds100": station.ds100,
+                "longname": station.longname,
+            },
+        })
+    });
+    let segments = station_map.segments.iter().map(|segment| {
+        json!({
+            "type": "Feature",
+            "geometry": {
+                "type": "LineString",
+                "coordinates": [
+                    [segment.from.coord.0, segment.from.coord.1],
+                    [segment.to.coord.0, segment.to.coord.1],
+                ],
+            },
+            "properties": {
+                "routenumber": segment.routenumber,
+                "from": segment.from.ds100,
+                "to": segment.to.ds100,
+            },
+        })
+    });
+    let collection = json!({
+        "type": "FeatureCollection",
+        "features": stations.chain(segments).collect::<Vec<_>>(),
+    });
+    serde_json::to_writer(&mut *writer, &collection)?;
+    writeln!(writer)?;
+    Ok(())
+}
+
+//
+// SVG
+//
+
+/// Size and orientation of an SVG export.
+#[derive(Debug, Clone, Copy)]
+pub struct SvgOptions {
+    pub width: u32,
+    pub height: u32,
+    /// Larger y coordinates are drawn further down, as the SVG y axis points. By default they
+    /// are drawn further up, as on a map.
+    pub flip_y: bool,
+}
+
+/// Margin around the map in pixels.
+const SVG_MARGIN: f64 = 10.0;
+
+/// The SVG image of `write_svg` as a string.
+pub fn to_svg(station_map: &StationMap, options: SvgOptions) -> String {
+    to_string(|writer| write_svg(writer, station_map, options))
+}
+
+/// Writes `station_map` as an SVG image, a line per segment and a circle per station. The map
+/// is scaled to fit the image with its aspect ratio kept.
+pub fn write_svg<W: Write + ?Sized>(
+    writer: &mut W,
+    station_map: &StationMap,
+    options: SvgOptions,
+) -> Result<()> {
+    let extent = calc_extent(&station_map.coordinates());
+    let (width, height) = (f64::from(options.width), f64::from(options.height));
+    let scale = ((width - 2.0 * SVG_MARGIN) / extent.width())
+        .min((height - 2.0 * SVG_MARGIN) / extent.height())
+        .max(0.0);
+    // centers the map on the axis it doesn't fill
+    let offset_x = (width - extent.width() * scale) / 2.0;
+    let offset_y = (height - extent.height() * scale) / 2.0;
+    let point = |(x, y): (f64, f64)| {
+        let row = if options.flip_y {
+            y - extent.min_y
+        } else {
+            extent.max_y - y
+        };
+        (
+            offset_x + (x - extent.min_x) * scale,
+            offset_y + row * scale,
+        )
+    };
+
+    writeln!(
+        writer,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="{1}" viewBox="0 0 {0} {1}">"#,
+        options.width, options.height
+    )?;
+    writeln!(writer, "<title>{}</title>", xml_escape(&station_map.name))?;
+    writeln!(writer, r#"<g stroke="gray" stroke-width="1">"#)?;
+    for segment in &station_map.segments {
+        let (x1, y1) = point(segment.from.coord);
+        let (x2, y2) = point(segment.to.coord);
+        writeln!(
+            writer,
+            r#"<line x1="{:.2}" y1="{:.2}" x2="{:.2}" y2="{:.2}"><title>{} ({} -> {})</title></line>"#,
+            x1,
+            y1,
+            x2,
+            y2,
+            segment.routenumber,
+            xml_escape(&segment.from.ds100),
+            xml_escape(&segment.to.ds100)
+        )?;
+    }
+    writeln!(writer, "</g>")?;
+    writeln!(writer, r#"<g fill="blue">"#)?;
+    for station in &station_map.stations {
+        let (x, y) = point(station.coord);
+        writeln!(
+            writer,
+            r#"<circle cx="{:.2}" cy="{:.2}" r="2"><title>{} ({})</title></circle>"#,
+            x,
+            y,
+            xml_escape(&station.ds100),
+            xml_escape(&station.longname)
+        )?;
+    }
+    writeln!(writer, "</g>")?;
+    writeln!(writer, "</svg>")?;
+    Ok(())
+}
+
+//
+// DOT
+//
+
+impl StationMap {
+    /// The undirected graph of `write_dot` as a string.
+    pub fn to_dot(&self) -> String {
+        to_string(|writer| write_dot(writer, self, false))
+    }
+
+    /// The directed graph of `write_dot` as a string, its edges point from `from` to `to`.
+    pub fn to_digraph(&self) -> String {
+        to_string(|writer| write_dot(writer, self, true))
+    }
+}
+
+/// Writes the network of `station_map` as a Graphviz graph: a node per `ds100` and an edge per
+/// segment labeled with its route number. A `directed` graph has edges from `from` to `to`.
+pub fn write_dot<W: Write + ?Sized>(
+    writer: &mut W,
+    station_map: &StationMap,
+    directed: bool,
+) -> Result<()> {
+    let (graph, edge) = if directed {
+        ("digraph", "->")
+    } else {
+        ("graph", "--")
+    };
+    writeln!(writer, "{} {} {{", graph, dot_id(&station_map.name))?;
+    let mut written = HashSet::new();
+    for station in &station_map.stations {
+        // merged maps can contain a ds100 twice, a node is written once
+        if written.insert(station.ds100.as_str()) {
+            writeln!(
+                writer,
+                "    {} [tooltip={}];",
+                dot_id(&station.ds100),
+                dot_id(&station.longname)
+            )?;
+        }
+    }
+    for segment in &station_map.segments {
+        writeln!(
+            writer,
+            "    {} {} {} [label={}];",
+            dot_id(&segment.from.ds100),
+            edge,
+            dot_id(&segment.to.ds100),
+            segment.routenumber
+        )?;
+    }
+    writeln!(writer, "}}")?;
+    Ok(())
+}
+
+/// The output of `write` as a string, writing to memory doesn't fail and the exports write UTF-8
+/// only.
+fn to_string<F>(write: F) -> String
+where
+    F: FnOnce(&mut Vec<u8>) -> Result<()>,
+{
+    let mut out = Vec::new();
+    write(&mut out).expect("Writing to memory failed");
+    String::from_utf8(out).expect("Export is not UTF-8")
+}
+
+/// Quotes `value` as a DOT id.
+fn dot_id(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Quotes `value` if it contains a separator, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{Segment, Station};
+
+    fn station(ds100: &str, longname: &str, coord: (f64, f64)) -> Station {
+        Station {
+            ds100: ds100.to_string(),
+            longname: longname.to_string(),
+            coord,
+        }
+    }
+
+    /// Three stations, `AA` twice as in a merged map, and two segments.
+    fn station_map() -> StationMap {
+        let alpha = station("AA", "Alpha \"Nord\"", (10.0, 50.0));
+        let beta = station("BB", "Beta", (11.0, 51.0));
+        let gamma = station("CC", "Gamma", (12.0, 52.0));
+        StationMap {
+            id: 1,
+            name: "Netz 2020".to_string(),
+            year: Some(2020),
+            stations: vec![
+                alpha.clone(),
+                beta.clone(),
+                gamma.clone(),
+                station("AA", "Alpha \"Nord\"", (10.0, 50.0)),
+            ],
+            segments: vec![
+                Segment {
+                    from: alpha,
+                    to: beta.clone(),
+                    routenumber: 1,
+                    electrified: Some(true),
+                },
+                Segment {
+                    from: beta,
+                    to: gamma,
+                    routenumber: 2,
+                    electrified: None,
+                },
+            ],
+            warnings: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn geojson_has_a_feature_per_station_and_segment() {
+        let geojson: serde_json::Value = serde_json::from_str(&station_map().to_geojson()).unwrap();
+
+        assert_eq!(geojson["type"], "FeatureCollection");
+        let features = geojson["features"].as_array().unwrap();
+        let count = |kind: &str| {
+            features
+                .iter()
+                .filter(|feature| feature["geometry"]["type"] == kind)
+                .count()
+        };
+        assert_eq!(count("Point"), 4);
+        assert_eq!(count("LineString"), 2);
+
+        assert_eq!(features[0]["geometry"]["coordinates"], json!([10.0, 50.0]));
+        assert_eq!(features[0]["properties"]["ds100"], "AA");
+        assert_eq!(features[0]["properties"]["longname"], "Alpha \"Nord\"");
+        assert_eq!(
+            features[5]["geometry"]["coordinates"],
+            json!([[11.0, 51.0], [12.0, 52.0]])
+        );
+        assert_eq!(features[5]["properties"]["routenumber"], 2);
+    }
+
+    /// The `cy` of each circle of `svg`.
+    fn circle_rows(svg: &str) -> Vec<f64> {
+        svg.lines()
+            .filter_map(|line| line.strip_prefix("<circle "))
+            .map(|circle| {
+                let cy = circle.split("cy=\"").nth(1).unwrap();
+                cy[..cy.find('"').unwrap()].parse().unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn svg_draws_a_circle_per_station_and_a_line_per_segment() {
+        let mut station_map = station_map();
+        station_map.stations.truncate(2);
+        station_map.segments.truncate(1);
+        let mut options = SvgOptions {
+            width: 200,
+            height: 100,
+            flip_y: false,
+        };
+        let svg = to_svg(&station_map, options);
+
+        assert!(svg.starts_with(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="200" height="100" viewBox="0 0 200 100">"#
+        ));
+        assert_eq!(svg.matches("<circle ").count(), 2);
+        assert_eq!(svg.matches("<line ").count(), 1);
+        assert!(svg.contains("<title>AA (Alpha &quot;Nord&quot;)</title>"));
+
+        // AA has the smaller y, it is drawn below BB unless the y axis is flipped
+        let rows = circle_rows(&svg);
+        assert!(rows.iter().all(|&row| (0.0..=100.0).contains(&row)));
+        assert!(rows[0] > rows[1]);
+        options.flip_y = true;
+        let rows = circle_rows(&to_svg(&station_map, options));
+        assert!(rows[0] < rows[1]);
+    }
+
+    #[test]
+    fn dot_writes_a_node_per_ds100_and_an_edge_per_segment() {
+        assert_eq!(
+            station_map().to_dot(),
+            r#"graph "Netz 2020" {
+    "AA" [tooltip="Alpha \"Nord\""];
+    "BB" [tooltip="Beta"];
+    "CC" [tooltip="Gamma"];
+    "AA" -- "BB" [label=1];
+    "BB" -- "CC" [label=2];
+}
+"#
+        );
+
+        let directed = station_map().to_digraph();
+        assert!(directed.starts_with("digraph \"Netz 2020\" {\n"));
+        assert!(directed.contains("    \"AA\" -> \"BB\" [label=1];\n"));
+        assert!(!directed.contains("--"));
+    }
+
+    #[test]
+    fn dot_ids_are_quoted() {
+        assert_eq!(dot_id("AA"), r#""AA""#);
+        assert_eq!(dot_id("Alpha \"Nord\""), r#""Alpha \"Nord\"""#);
+        assert_eq!(dot_id(r"A\B"), r#""A\\B""#);
+        assert_eq!(dot_id("Köln Hbf"), r#""Köln Hbf""#);
+    }
+}
@@ -0,0 +1,106 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+/// Maximum number of entries kept in the history.
+const HISTORY_CAPACITY: usize = 100;
+
+//
+// History
+//
+
+/// The previous queries of the prompts, the most recent last. Stored in a file with one query
+/// per line.
+pub struct History {
+    entries: Vec<String>,
+    position: Option<usize>, // index into entries while browsing
+    path: Option<PathBuf>,
+}
+
+impl History {
+    /// Loads the history from `path`, a missing or unreadable file starts an empty history.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let entries = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|content| {
+                content
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        History {
+            entries,
+            position: None,
+            path,
+        }
+    }
+
+    /// `<os data dir>/tie/history`, if the os has a data dir.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("tie").join("history"))
+    }
+
+    /// Appends `entry` and stops browsing. Repeating the last entry doesn't add it again.
+    pub fn push(&mut self, entry: &str) {
+        self.position = None;
+        let entry = entry.trim();
+        if entry.is_empty() || self.entries.last().is_some_and(|last| last == entry) {
+            return;
+        }
+
+        self.entries.push(entry.to_string());
+        if self.entries.len() > HISTORY_CAPACITY {
+            let excess = self.entries.len() - HISTORY_CAPACITY;
+            self.entries.drain(..excess);
+        }
+    }
+
+    /// The previous (older) entry while browsing, starting with the most recent one.
+    pub fn prev(&mut self) -> Option<&str> {
+        let position = match self.position {
+            Some(position) => position.checked_sub(1)?,
+            None => self.entries.len().checked_sub(1)?,
+        };
+        self.position = Some(position);
+        self.entries.get(position).map(String::as_str)
+    }
+
+    /// The next (newer) entry while browsing, `None` after the most recent one.
+    pub fn next(&mut self) -> Option<&str> {
+        let position = self.position? + 1;
+        if position >= self.entries.len() {
+            self.position = None;
+            return None;
+        }
+        self.position = Some(position);
+        self.entries.get(position).map(String::as_str)
+    }
+
+    /// Stops browsing, the next `prev` starts with the most recent entry again.
+    pub fn reset(&mut self) {
+        self.position = None;
+    }
+
+    pub fn save(&self) -> Result<()> {
+        match self.path {
+            Some(ref path) => write_history(path, &self.entries),
+            None => Ok(()),
+        }
+    }
+}
+
+fn write_history(path: &Path, entries: &[String]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Could not create history dir '{}'", dir.display()))?;
+    }
+    let mut content = entries.join("\n");
+    content.push('\n');
+    fs::write(path, content)
+        .with_context(|| format!("Could not write history file '{}'", path.display()))
+}
@@ -0,0 +1,464 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use anyhow::{anyhow, bail, Error, Result};
+use serde::Deserialize;
+use termion::event::Key;
+
+//
+// Command
+//
+
+/// A named command keys are bound to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Command {
+    Quit,
+    Back,
+    Select,
+    Up,
+    Down,
+    FocusStations,
+    FocusSegments,
+    FocusMap,
+    ToggleElectrification,
+    ToggleHighlight,
+    Retry,
+    OpenPalette,
+    AddToRoute,
+    SaveRoute,
+    ToggleHistogram,
+    CycleSort,
+    ReverseSort,
+    ShowNeighbors,
+    ToggleStationsLayer,
+    ToggleSegmentsLayer,
+    OpenWebPage,
+    FitSelection,
+    ShowView,
+    ToggleDirected,
+    Filter,
+    ZoomIn,
+    ZoomOut,
+    ResetZoom,
+    Left,
+    Right,
+    CycleFocus,
+    ToggleLabels,
+    ToggleHelp,
+    PageUp,
+    PageDown,
+    First,
+    Last,
+    CycleFocusBack,
+    ShowStats,
+    ToggleBookmark,
+    NextBookmark,
+    PreviousBookmark,
+}
+
+impl Command {
+    pub const ALL: [Command; 42] = [
+        Command::Quit,
+        Command::Back,
+        Command::Select,
+        Command::Up,
+        Command::Down,
+        Command::FocusStations,
+        Command::FocusSegments,
+        Command::FocusMap,
+        Command::ToggleElectrification,
+        Command::ToggleHighlight,
+        Command::Retry,
+        Command::OpenPalette,
+        Command::AddToRoute,
+        Command::SaveRoute,
+        Command::ToggleHistogram,
+        Command::CycleSort,
+        Command::ReverseSort,
+        Command::ShowNeighbors,
+        Command::ToggleStationsLayer,
+        Command::ToggleSegmentsLayer,
+        Command::OpenWebPage,
+        Command::FitSelection,
+        Command::ShowView,
+        Command::ToggleDirected,
+        Command::Filter,
+        Command::ZoomIn,
+        Command::ZoomOut,
+        Command::ResetZoom,
+        Command::Left,
+        Command::Right,
+        Command::CycleFocus,
+        Command::ToggleLabels,
+        Command::ToggleHelp,
+        Command::PageUp,
+        Command::PageDown,
+        Command::First,
+        Command::Last,
+        Command::CycleFocusBack,
+        Command::ShowStats,
+        Command::ToggleBookmark,
+        Command::NextBookmark,
+        Command::PreviousBookmark,
+    ];
+
+    /// The name used in the config.
+    pub fn name(self) -> &'static str {
+        match self {
+            Command::Quit => "quit",
+            Command::Back => "back",
+            Command::Select => "select",
+            Command::Up => "up",
+            Command::Down => "down",
+            Command::FocusStations => "focus_stations",
+            Command::FocusSegments => "focus_segments",
+            Command::FocusMap => "focus_map",
+            Command::ToggleElectrification => "toggle_electrification",
+            Command::ToggleHighlight => "toggle_highlight",
+            Command::Retry => "retry",
+            Command::OpenPalette => "open_palette",
+            Command::AddToRoute => "add_to_route",
+            Command::SaveRoute => "save_route",
+            Command::ToggleHistogram => "toggle_histogram",
+            Command::CycleSort => "cycle_sort",
+            Command::ReverseSort => "reverse_sort",
+            Command::ShowNeighbors => "show_neighbors",
+            Command::ToggleStationsLayer => "toggle_stations_layer",
+            Command::ToggleSegmentsLayer => "toggle_segments_layer",
+            Command::OpenWebPage => "open_web_page",
+            Command::FitSelection => "fit_selection",
+            Command::ShowView => "show_view",
+            Command::ToggleDirected => "toggle_directed",
+            Command::Filter => "filter",
+            Command::ZoomIn => "zoom_in",
+            Command::ZoomOut => "zoom_out",
+            Command::ResetZoom => "reset_zoom",
+            Command::Left => "left",
+            Command::Right => "right",
+            Command::CycleFocus => "cycle_focus",
+            Command::ToggleLabels => "toggle_labels",
+            Command::ToggleHelp => "toggle_help",
+            Command::PageUp => "page_up",
+            Command::PageDown => "page_down",
+            Command::First => "first",
+            Command::Last => "last",
+            Command::CycleFocusBack => "cycle_focus_back",
+            Command::ShowStats => "show_stats",
+            Command::ToggleBookmark => "toggle_bookmark",
+            Command::NextBookmark => "next_bookmark",
+            Command::PreviousBookmark => "previous_bookmark",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Command::Quit => "Beenden",
+            Command::Back => "Zurück",
+            Command::Select => "Auswählen",
+            Command::Up => "Nach oben",
+            Command::Down => "Nach unten",
+            Command::FocusStations => "Betriebsstellen fokussieren",
+            Command::FocusSegments => "Streckensegmente fokussieren",
+            Command::FocusMap => "Karte fokussieren",
+            Command::ToggleElectrification => "Elektrifizierungsfilter wechseln",
+            Command::ToggleHighlight => "Hervorhebung wechseln",
+            Command::Retry => "Erneut versuchen",
+            Command::OpenPalette => "Befehlspalette öffnen",
+            Command::AddToRoute => "Betriebsstelle zur Route hinzufügen",
+            Command::SaveRoute => "Route speichern",
+            Command::ToggleHistogram => "Histogramm der Streckennummern ein-/ausblenden",
+            Command::CycleSort => "Sortierung wechseln",
+            Command::ReverseSort => "Sortierrichtung umkehren",
+            Command::ShowNeighbors => "Nachbarn der Betriebsstelle anzeigen",
+            Command::ToggleStationsLayer => "Betriebsstellen auf der Karte ein-/ausblenden",
+            Command::ToggleSegmentsLayer => "Streckensegmente auf der Karte ein-/ausblenden",
+            Command::OpenWebPage => "Infrastruktur im Trassenfinder öffnen",
+            Command::FitSelection => "Karte an die Auswahl anpassen",
+            Command::ShowView => "Kartenausschnitt als --bbox anzeigen",
+            Command::ToggleDirected => "Segmente gerichtet/ungerichtet auswerten",
+            Command::Filter => {
+                "Infrastrukturen filtern, Betriebsstelle suchen bzw. Strecken filtern"
+            }
+            Command::ZoomIn => "Karte vergrößern",
+            Command::ZoomOut => "Karte verkleinern",
+            Command::ResetZoom => "Ganze Karte anzeigen",
+            Command::Left => "Nach links",
+            Command::Right => "Nach rechts",
+            Command::CycleFocus => "Nächsten Bereich fokussieren",
+            Command::ToggleLabels => "Beschriftung der Betriebsstellen ein-/ausblenden",
+            Command::ToggleHelp => "Hilfe ein-/ausblenden",
+            Command::PageUp => "Eine Seite nach oben",
+            Command::PageDown => "Eine Seite nach unten",
+            Command::First => "Zum ersten Eintrag",
+            Command::Last => "Zum letzten Eintrag",
+            Command::CycleFocusBack => "Vorherigen Bereich fokussieren",
+            Command::ShowStats => "Kennzahlen der Infrastruktur anzeigen",
+            Command::ToggleBookmark => "Lesezeichen der Betriebsstelle setzen/entfernen",
+            Command::NextBookmark => "Zum nächsten Lesezeichen",
+            Command::PreviousBookmark => "Zum vorherigen Lesezeichen",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Command> {
+        Command::ALL
+            .iter()
+            .cloned()
+            .find(|command| command.name() == name)
+    }
+}
+
+//
+// KeyMap
+//
+
+/// Maps keys to commands. In the config a command is bound to one key or a list of keys,
+/// which replace its default keys:
+///
+/// ```toml
+/// [keys]
+/// up = ["k", "Up"]
+/// quit = "Ctrl-q"
+/// ```
+#[derive(Deserialize, Debug, Clone)]
+#[serde(try_from = "HashMap<String, Bindings>")]
+pub struct KeyMap {
+    commands: HashMap<Key, Command>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum Bindings {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        let commands = [
+            (Key::Char('q'), Command::Quit),
+            (Key::Esc, Command::Back),
+            (Key::Char('\n'), Command::Select),
+            (Key::Up, Command::Up),
+            (Key::Char('k'), Command::Up),
+            (Key::Down, Command::Down),
+            (Key::Char('j'), Command::Down),
+            (Key::Char('b'), Command::FocusStations),
+            (Key::Char('s'), Command::FocusSegments),
+            (Key::Char('m'), Command::FocusMap),
+            (Key::Char('e'), Command::ToggleElectrification),
+            (Key::Char('v'), Command::ToggleHighlight),
+            (Key::Char('r'), Command::Retry),
+            (Key::Char(':'), Command::OpenPalette),
+            (Key::Char('a'), Command::AddToRoute),
+            (Key::Char('w'), Command::SaveRoute),
+            (Key::Char('c'), Command::ToggleHistogram),
+            (Key::Char('t'), Command::CycleSort),
+            (Key::Char('T'), Command::ReverseSort),
+            (Key::Char('n'), Command::ShowNeighbors),
+            (Key::Char('1'), Command::ToggleStationsLayer),
+            (Key::Char('2'), Command::ToggleSegmentsLayer),
+            (Key::Char('o'), Command::OpenWebPage),
+            (Key::Char('f'), Command::FitSelection),
+            (Key::Char('x'), Command::ShowView),
+            (Key::Char('d'), Command::ToggleDirected),
+            (Key::Char('/'), Command::Filter),
+            (Key::Char('+'), Command::ZoomIn),
+            (Key::Char('-'), Command::ZoomOut),
+            (Key::Char('0'), Command::ResetZoom),
+            (Key::Left, Command::Left),
+            (Key::Char('h'), Command::Left),
+            (Key::Right, Command::Right),
+            (Key::Char('l'), Command::Right),
+            (Key::Char('\t'), Command::CycleFocus),
+            (Key::Char('L'), Command::ToggleLabels),
+            (Key::Char('?'), Command::ToggleHelp),
+            (Key::PageUp, Command::PageUp),
+            (Key::PageDown, Command::PageDown),
+            (Key::Home, Command::First),
+            (Key::End, Command::Last),
+            (Key::BackTab, Command::CycleFocusBack),
+            (Key::Char('i'), Command::ShowStats),
+            (Key::Char('M'), Command::ToggleBookmark),
+            (Key::Char(']'), Command::NextBookmark),
+            (Key::Char('['), Command::PreviousBookmark),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+
+        KeyMap { commands }
+    }
+}
+
+impl TryFrom<HashMap<String, Bindings>> for KeyMap {
+    type Error = Error;
+
+    /// A key bound to two commands is an error, so the result doesn't depend on the order of
+    /// `bindings`.
+    fn try_from(bindings: HashMap<String, Bindings>) -> Result<Self> {
+        let mut keymap = KeyMap::default();
+        let mut bound: HashMap<Key, Command> = HashMap::new();
+        for (name, bindings) in bindings {
+            let command = match Command::from_name(&name) {
+                Some(command) => command,
+                None => bail!("Unknown command '{}'", name),
+            };
+            let names = match bindings {
+                Bindings::One(name) => vec![name],
+                Bindings::Many(names) => names,
+            };
+            let keys = names
+                .iter()
+                .map(|name| parse_key(name))
+                .collect::<Result<Vec<Key>>>()
+                .map_err(|error| anyhow!("{} for command '{}'", error, name))?;
+
+            for &key in &keys {
+                if let Some(other) = bound.insert(key, command).filter(|&other| other != command) {
+                    let mut names = [other.name(), command.name()];
+                    names.sort_unstable();
+                    bail!(
+                        "Key '{}' is bound to command '{}' and '{}'",
+                        key_name(key),
+                        names[0],
+                        names[1]
+                    );
+                }
+            }
+
+            keymap.commands.retain(|_, c| *c != command);
+            for key in keys {
+                keymap.commands.insert(key, command);
+            }
+        }
+        Ok(keymap)
+    }
+}
+
+impl KeyMap {
+    pub fn command(&self, key: Key) -> Option<Command> {
+        self.commands.get(&key).cloned()
+    }
+
+    /// The keys bound to `command`, ordered by name.
+    pub fn keys(&self, command: Command) -> Vec<Key> {
+        let mut keys: Vec<Key> = self
+            .commands
+            .iter()
+            .filter(|&(_, &c)| c == command)
+            .map(|(&key, _)| key)
+            .collect();
+        keys.sort_by_key(|&key| key_name(key));
+        keys
+    }
+}
+
+/// The name of `key` as `parse_key` reads it.
+pub fn key_name(key: Key) -> String {
+    match key {
+        Key::Char(' ') => "Space".to_string(),
+        Key::Char('\n') => "Enter".to_string(),
+        Key::Char('\t') => "Tab".to_string(),
+        Key::Char(c) => c.to_string(),
+        Key::Ctrl(c) => format!("Ctrl-{}", c),
+        Key::Alt(c) => format!("Alt-{}", c),
+        Key::F(n) => format!("F{}", n),
+        Key::Esc => "Esc".to_string(),
+        Key::Backspace => "Backspace".to_string(),
+        Key::Delete => "Delete".to_string(),
+        Key::Insert => "Insert".to_string(),
+        Key::Left => "Left".to_string(),
+        Key::Right => "Right".to_string(),
+        Key::Up => "Up".to_string(),
+        Key::Down => "Down".to_string(),
+        Key::Home => "Home".to_string(),
+        Key::End => "End".to_string(),
+        Key::PageUp => "PageUp".to_string(),
+        Key::PageDown => "PageDown".to_string(),
+        Key::BackTab => "BackTab".to_string(),
+        _ => "?".to_string(),
+    }
+}
+
+/// Parses a key name like `q`, `Up`, `Enter`, `F5`, `Ctrl-c` or `Alt-x`.
+pub fn parse_key(name: &str) -> Result<Key> {
+    let single_char = |s: &str| {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ => None,
+        }
+    };
+
+    if let Some(c) = single_char(name) {
+        return Ok(Key::Char(c));
+    }
+    if let Some(c) = name.strip_prefix("Ctrl-").and_then(single_char) {
+        return Ok(Key::Ctrl(c));
+    }
+    if let Some(c) = name.strip_prefix("Alt-").and_then(single_char) {
+        return Ok(Key::Alt(c));
+    }
+    if let Some(n) = name.strip_prefix('F').and_then(|n| n.parse::<u8>().ok()) {
+        if (1..=12).contains(&n) {
+            return Ok(Key::F(n));
+        }
+    }
+
+    Ok(match name {
+        "Space" => Key::Char(' '),
+        "Enter" => Key::Char('\n'),
+        "Tab" => Key::Char('\t'),
+        "Esc" => Key::Esc,
+        "Backspace" => Key::Backspace,
+        "Delete" => Key::Delete,
+        "Insert" => Key::Insert,
+        "Left" => Key::Left,
+        "Right" => Key::Right,
+        "Up" => Key::Up,
+        "Down" => Key::Down,
+        "Home" => Key::Home,
+        "End" => Key::End,
+        "PageUp" => Key::PageUp,
+        "PageDown" => Key::PageDown,
+        "BackTab" => Key::BackTab,
+        _ => bail!("Unknown key '{}'", name),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bindings_replace_the_default_keys() {
+        let keymap: KeyMap = toml::from_str(
+            r#"
+            up = ["x", "k"]
+            quit = "j"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            keymap.keys(Command::Up),
+            vec![Key::Char('k'), Key::Char('x')]
+        );
+        assert_eq!(keymap.command(Key::Up), None);
+        assert_eq!(keymap.command(Key::Char('j')), Some(Command::Quit));
+        assert_eq!(keymap.command(Key::Char('q')), None);
+    }
+
+    #[test]
+    fn a_key_bound_twice_is_an_error() {
+        let error = toml::from_str::<KeyMap>(
+            r#"
+            up = "x"
+            down = ["j", "x"]
+            "#,
+        )
+        .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("Key 'x' is bound to command 'down' and 'up'"));
+    }
+}
@@ -1,17 +1,46 @@
-use std::io;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use structopt::StructOpt;
 use termion::event::Key;
+use termion::input::MouseTerminal;
 use termion::raw::IntoRawMode;
-use tui::backend::TermionBackend;
+use termion::screen::AlternateScreen;
+use tui::backend::{Backend, TermionBackend};
 use tui::Terminal;
 
-use crate::events::Event;
-use crate::widgets::{InfrastrukturSelectionWidget, TermWidget};
+use crate::api::{DataSource, Extent, SearchProgress, SourceOptions, StationMap};
+use crate::app::App;
+use crate::cache::Cache;
+use crate::config::Config;
+use crate::events::{Event, ModesTerminal, ANY_MOTION, BRACKETED_PASTE};
+use crate::export::SvgOptions;
+use crate::state::State;
+use crate::widgets::Action;
 
 mod api;
+mod app;
+mod cache;
+mod config;
 mod events;
+mod export;
+mod history;
+mod keymap;
+mod prefetch;
+mod ratelimit;
+mod report;
+mod route;
+mod spatial;
+mod state;
 mod widgets;
 
 #[derive(StructOpt, Debug)]
@@ -23,52 +52,396 @@ struct Opt {
         default_value = "https://www.trassenfinder.de/api/web/infrastrukturen"
     )]
     api_url: String,
+
+    /// Config file [default: <config dir>/tie/config.toml]
+    #[structopt(short, long, parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Draw with ASCII characters only
+    #[structopt(long)]
+    ascii: bool,
+
+    /// Draw without colors and text styles, also set by the `NO_COLOR` environment variable
+    #[structopt(long)]
+    no_color: bool,
+
+    /// Follow segments only from `from` to `to` for neighbors and components
+    #[structopt(long)]
+    directed: bool,
+
+    /// Draw larger y coordinates further down, use it if the map is upside down
+    #[structopt(long)]
+    flip_y: bool,
+
+    /// Leave out segments with unknown stations (listed as warnings) instead of failing
+    #[structopt(long)]
+    lenient: bool,
+
+    /// Proxy url for all requests, overrides HTTP_PROXY/HTTPS_PROXY/NO_PROXY
+    #[structopt(long)]
+    proxy: Option<String>,
+
+    /// Maximum requests per second sent to the api
+    #[structopt(long, value_name = "rps", default_value = "5")]
+    max_rps: f64,
+
+    /// Seconds to wait for a response, including connecting
+    #[structopt(long, value_name = "secs", default_value = "30")]
+    timeout: u64,
+
+    /// How often failed requests (connection errors, 5xx responses) are retried
+    #[structopt(long, default_value = "3")]
+    retries: u32,
+
+    /// Cache directory [default: <cache dir>/tie]
+    #[structopt(long, parse(from_os_str))]
+    cache_dir: Option<PathBuf>,
+
+    /// Neither read nor write the cache
+    #[structopt(long)]
+    no_cache: bool,
+
+    /// Request uncompressed responses, to debug the raw payloads
+    #[structopt(long)]
+    no_compression: bool,
+
+    /// Route file, a route is highlighted on the map and saved with `w`
+    #[structopt(long, parse(from_os_str))]
+    route: Option<PathBuf>,
+
+    /// Open the map of an infrastructure, the maps of several ids are merged into one
+    #[structopt(long = "id", alias = "infrastructure-id", value_name = "id")]
+    ids: Vec<u64>,
+
+    /// Open the map of an infrastructure saved as json, instead of one from the api
+    #[structopt(long, parse(from_os_str), value_name = "path", conflicts_with = "ids")]
+    file: Option<PathBuf>,
+
+    /// Only show stations inside the bounding box and segments touching it
+    #[structopt(long, value_name = "min_x,min_y,max_x,max_y")]
+    bbox: Option<Extent>,
+
+    /// Print the distance matrix between all stations of an infrastructure as CSV
+    #[structopt(long, value_name = "id")]
+    matrix: Option<u64>,
+
+    /// Write the maps of the `--id`s as GeoJSON to the file, `-` writes to stdout
+    #[structopt(long, value_name = "path", parse(from_os_str))]
+    export_geojson: Option<PathBuf>,
+
+    /// Write the maps of the `--id`s as `stations.csv` and `segments.csv` to the directory
+    #[structopt(long, value_name = "dir", parse(from_os_str))]
+    export_csv: Option<PathBuf>,
+
+    /// Write the maps of the `--id`s as SVG image to the file, `-` writes to stdout
+    #[structopt(long, value_name = "path", parse(from_os_str))]
+    export_svg: Option<PathBuf>,
+
+    /// Write the networks of the `--id`s as Graphviz DOT graph to the file, `-` writes to stdout
+    #[structopt(long, value_name = "path", parse(from_os_str))]
+    export_dot: Option<PathBuf>,
+
+    /// Size of the `--export-svg` image
+    #[structopt(long, value_name = "width>x<height", default_value = "1000x1000")]
+    svg_size: SvgSize,
+
+    /// Print a Markdown report of an infrastructure
+    #[structopt(long, value_name = "id")]
+    report: Option<u64>,
+
+    /// Print the connected components of the network of an infrastructure, largest first
+    #[structopt(long, value_name = "id")]
+    components: Option<u64>,
+
+    /// Find the infrastructures containing a station, by ds100 or name
+    #[structopt(long, value_name = "ds100-or-name")]
+    find_station: Option<String>,
+
+    /// Neither show nor remember the infrastructures recently opened from the list
+    #[structopt(long)]
+    no_remember: bool,
+
+    /// Only list (and search) the infrastructures of this timetable year
+    #[structopt(long)]
+    year: Option<u32>,
+
+    /// Confirm printing a distance matrix with more than 500 stations
+    #[structopt(long)]
+    confirm: bool,
 }
 
+/// Stations above which a distance matrix needs `--confirm`.
+const MATRIX_CONFIRM_LIMIT: usize = 500;
+
 fn main() -> Result<()> {
     // Arguments
     let opt: Opt = Opt::from_args();
-    let api_url = &opt.api_url;
+    let mut config = Config::load(opt.config.as_deref())?;
+    config.ascii |= opt.ascii;
+    // https://no-color.org: set and not empty
+    config.no_color |=
+        opt.no_color || env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    config.flip_y |= opt.flip_y;
+    config.lenient |= opt.lenient;
+    config.directed |= opt.directed;
+    config.route_file = opt.route;
+    config.bbox = opt.bbox;
+    config.year = opt.year;
+    let cache_dir = opt.cache_dir.clone().or_else(Cache::default_dir);
+    let source = DataSource::new(
+        &opt.api_url,
+        &SourceOptions {
+            proxy: opt.proxy.as_deref(),
+            max_rps: opt.max_rps,
+            timeout: Duration::from_secs(opt.timeout),
+            retries: opt.retries,
+            cache_dir: if opt.no_cache {
+                None
+            } else {
+                cache_dir.as_deref()
+            },
+            lenient: config.lenient,
+            compression: !opt.no_compression,
+        },
+    )?;
+
+    if let Some(id) = opt.matrix {
+        let mut station_map = source.read_station_map(id)?;
+        if let Some(bbox) = opt.bbox {
+            station_map = station_map.filter_bbox(bbox);
+        }
+        let count = station_map.stations.len();
+        if count > MATRIX_CONFIRM_LIMIT && !opt.confirm {
+            bail!(
+                "The distance matrix of {} stations has {} entries, use --confirm to print it",
+                count,
+                count * count
+            );
+        }
+        return export::write_distance_matrix(&mut io::stdout().lock(), &station_map);
+    }
 
-    // Widgets
-    let mut termwidget =
-        TermWidget::InfrastrukturSelection(InfrastrukturSelectionWidget::from_url(api_url)?);
+    if let Some(ref path) = opt.export_geojson {
+        let station_map = read_export_map(&source, &opt.ids, opt.bbox)?;
+        return write_export_text(path, &station_map.to_geojson());
+    }
+
+    if let Some(ref path) = opt.export_svg {
+        let station_map = read_export_map(&source, &opt.ids, opt.bbox)?;
+        let options = SvgOptions {
+            width: opt.svg_size.width,
+            height: opt.svg_size.height,
+            flip_y: config.flip_y,
+        };
+        return write_export_text(path, &export::to_svg(&station_map, options));
+    }
+
+    if let Some(ref path) = opt.export_dot {
+        let station_map = read_export_map(&source, &opt.ids, opt.bbox)?;
+        let dot = if config.directed {
+            station_map.to_digraph()
+        } else {
+            station_map.to_dot()
+        };
+        return write_export_text(path, &dot);
+    }
+
+    if let Some(ref dir) = opt.export_csv {
+        let station_map = read_export_map(&source, &opt.ids, opt.bbox)?;
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Could not create directory '{}'", dir.display()))?;
+        write_export(&dir.join("stations.csv"), |writer| {
+            export::write_stations_csv(writer, &station_map)
+        })?;
+        return write_export(&dir.join("segments.csv"), |writer| {
+            export::write_segments_csv(writer, &station_map)
+        });
+    }
+
+    if let Some(id) = opt.report {
+        let station_map = source.read_station_map(id)?;
+        print!("{}", report::to_markdown(&station_map));
+        return Ok(());
+    }
+
+    if let Some(id) = opt.components {
+        let station_map = source.read_station_map(id)?;
+        print!("{}", report::components(&station_map, !config.directed));
+        return Ok(());
+    }
+
+    if let Some(ref query) = opt.find_station {
+        let mut fetched = 0;
+        let found = api::search_station(&source, query, opt.year, |progress| match progress {
+            SearchProgress::Page(page) => eprintln!("page {}", page),
+            SearchProgress::Infrastructure(info, result) => {
+                fetched += 1;
+                match result {
+                    Ok(_) => eprintln!("[{}] {}", fetched, info.anzeigename),
+                    Err(error) => eprintln!("[{}] {}: {:#}", fetched, info.anzeigename, error),
+                }
+            }
+        })?;
+        for (id, station) in found {
+            println!("{}: {} ({})", id, station.ds100, station.longname);
+        }
+        return Ok(());
+    }
+
+    // App
+    let action = match opt.file {
+        Some(ref path) => Action::OpenFile(path.clone()),
+        None if opt.ids.is_empty() => Action::FetchInfos,
+        None => Action::FetchMap(opt.ids.clone()),
+    };
+    let tick_rate = config.tick_rate();
+    let state = State::load(if opt.no_remember {
+        None
+    } else {
+        State::default_path()
+    });
+    let mut app = App::new(source, config, action, state)?;
 
     // Terminal
-    let stdout = io::stdout().into_raw_mode()?;
+    // a panic unwinds through the terminal wrappers, which restore the terminal. The messages of
+    // the panics of all threads are printed afterwards, in raw mode they would be garbled
+    let previous_hook = panic::take_hook();
+    panic::set_hook(Box::new(|info| {
+        let message = format!(
+            "thread '{}' {}",
+            thread::current().name().unwrap_or("<unnamed>"),
+            info
+        );
+        if let Ok(mut messages) = PANIC_MESSAGES.lock() {
+            messages.push(message);
+        }
+    }));
+    let result = panic::catch_unwind(AssertUnwindSafe(|| run_terminal(&mut app, tick_rate)));
+    panic::set_hook(previous_hook);
+    if let Ok(mut messages) = PANIC_MESSAGES.lock() {
+        for message in messages.drain(..) {
+            eprintln!("{}", message);
+        }
+    }
+    match result {
+        Ok(result) => result,
+        Err(_) => process::exit(101),
+    }
+}
+
+/// The messages of the panics while the terminal is in raw mode.
+static PANIC_MESSAGES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Runs `app` in the alternate screen until it quits or Ctrl-c is pressed. The terminal is
+/// restored on every return.
+fn run_terminal(app: &mut App, tick_rate: Duration) -> Result<()> {
+    // the alternate screen keeps the shell output, it is shown again on exit
+    let stdout = AlternateScreen::from(ModesTerminal::new(
+        MouseTerminal::from(io::stdout().into_raw_mode()?),
+        &[BRACKETED_PASTE, ANY_MOTION],
+    ));
     let backend = TermionBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
     terminal.hide_cursor()?;
     terminal.clear()?;
 
-    let input_events = events::Events::new();
+    let input_events = events::Events::new(tick_rate);
+    run_events(&mut terminal, app, &input_events)
+}
 
-    loop {
-        terminal.draw(|mut f| {
-            let area = f.size();
-            termwidget.render(&mut f, area);
-        })?;
+fn run_events<B: Backend>(
+    terminal: &mut Terminal<B>,
+    app: &mut App,
+    input_events: &events::Events,
+) -> Result<()> {
+    let mut redraw = true;
+    while app.is_running() {
+        if redraw {
+            terminal.draw(|mut f| app.draw(&mut f))?;
+        }
 
-        let next_termwidget = match input_events.next()? {
-            Event::Input(key) => match key {
-                Key::Char('q') => {
-                    terminal.clear()?;
-                    break;
-                }
-                key @ _ => match termwidget {
-                    TermWidget::InfrastrukturSelection(ref mut widget) => {
-                        widget.select_key(key, api_url)
-                    }
-                    TermWidget::Map(ref mut widget) => widget.select_key(key, api_url),
-                },
-            },
-        }?;
+        redraw = match input_events.next()? {
+            // raw mode turns Ctrl-c into a key, it quits even if `quit` is bound to other keys
+            Event::Input(Key::Ctrl('c')) => break,
+            Event::Input(key) => {
+                app.handle_key(key);
+                true
+            }
+            Event::Mouse(event) => {
+                app.handle_mouse(event);
+                true
+            }
+            Event::Motion(x, y) => app.handle_motion(x, y),
+            Event::Paste(text) => {
+                app.handle_paste(&text);
+                true
+            }
+            Event::Resize => {
+                // cells of the old size can remain after a resize
+                terminal.clear()?;
+                true
+            }
+            Event::Tick => app.tick(),
+        };
+    }
+    Ok(())
+}
+
+/// The size of an image in pixels, `<width>x<height>`.
+#[derive(Debug, Clone, Copy)]
+struct SvgSize {
+    width: u32,
+    height: u32,
+}
 
-        if let Some(next_widget) = next_termwidget {
-            termwidget = next_widget;
+impl FromStr for SvgSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let size = s
+            .split_once('x')
+            .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)));
+        match size {
+            Some((width, height)) if width > 0 && height > 0 => Ok(SvgSize { width, height }),
+            _ => bail!(
+                "Invalid size '{}', expected <width>x<height>, e.g. 800x600",
+                s
+            ),
         }
     }
+}
 
-    Ok(())
+/// The merged maps of the `--id`s, limited to `--bbox`, for the exports.
+fn read_export_map(source: &DataSource, ids: &[u64], bbox: Option<Extent>) -> Result<StationMap> {
+    if ids.is_empty() {
+        bail!("The export needs at least one --id");
+    }
+    let station_map = source.read_merged_station_map(ids)?;
+    Ok(match bbox {
+        Some(bbox) => station_map.filter_bbox(bbox),
+        None => station_map,
+    })
+}
+
+/// Writes `text` to `path`, see `write_export`.
+fn write_export_text(path: &Path, text: &str) -> Result<()> {
+    write_export(path, |writer| Ok(writer.write_all(text.as_bytes())?))
+}
+
+/// Runs `write` on the file `path`, or on stdout for `-`.
+fn write_export<F>(path: &Path, write: F) -> Result<()>
+where
+    F: FnOnce(&mut dyn Write) -> Result<()>,
+{
+    if path == Path::new("-") {
+        return write(&mut io::stdout().lock());
+    }
+    let mut file = BufWriter::new(
+        File::create(path)
+            .with_context(|| format!("Could not create file '{}'", path.display()))?,
+    );
+    write(&mut file)
+        .and_then(|()| file.flush().map_err(anyhow::Error::from))
+        .with_context(|| format!("Could not write file '{}'", path.display()))
 }
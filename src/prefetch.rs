@@ -0,0 +1,129 @@
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use anyhow::Result;
+
+use crate::api::StationMap;
+
+//
+// Prefetcher
+//
+
+/// Fetches station maps in the background and keeps the most recently used ones.
+#[derive(Clone)]
+pub struct Prefetcher {
+    state: Arc<(Mutex<State>, Condvar)>,
+}
+
+struct State {
+    wanted: HashSet<u64>,
+    in_flight: HashSet<u64>,
+    station_maps: LruCache<u64, StationMap>,
+}
+
+impl Prefetcher {
+    pub fn new(capacity: usize) -> Self {
+        Prefetcher {
+            state: Arc::new((
+                Mutex::new(State {
+                    wanted: HashSet::new(),
+                    in_flight: HashSet::new(),
+                    station_maps: LruCache::new(capacity),
+                }),
+                Condvar::new(),
+            )),
+        }
+    }
+
+    /// Fetches the station maps for `ids` in background threads. Only the `ids` of the most
+    /// recent call are wanted, results of fetches that are no longer wanted are dropped.
+    pub fn prefetch<F>(&self, ids: &[u64], fetch: F)
+    where
+        F: Fn(u64) -> Result<StationMap> + Clone + Send + 'static,
+    {
+        let mut state = self.state.0.lock().unwrap();
+        state.wanted = ids.iter().cloned().collect();
+
+        for &id in ids {
+            if state.in_flight.contains(&id) || state.station_maps.contains(&id) {
+                continue;
+            }
+            state.in_flight.insert(id);
+
+            let shared_state = Arc::clone(&self.state);
+            let fetch = fetch.clone();
+            thread::spawn(move || {
+                let (ref state, ref fetched) = *shared_state;
+                {
+                    // the selection may have moved on before this thread ran
+                    let mut state = state.lock().unwrap();
+                    if !state.wanted.contains(&id) {
+                        state.in_flight.remove(&id);
+                        fetched.notify_all();
+                        return;
+                    }
+                }
+                let result = fetch(id);
+
+                let mut state = state.lock().unwrap();
+                state.in_flight.remove(&id);
+                if let Ok(station_map) = result {
+                    if state.wanted.contains(&id) {
+                        state.station_maps.put(id, station_map);
+                    }
+                }
+                fetched.notify_all();
+            });
+        }
+    }
+
+    /// The prefetched station map `id`, waits for it if its fetch is in flight. `None` if it
+    /// was not prefetched or its fetch failed.
+    pub fn get(&self, id: u64) -> Option<StationMap> {
+        let (ref state, ref fetched) = *self.state;
+        let mut state = state.lock().unwrap();
+        while state.in_flight.contains(&id) {
+            state = fetched.wait(state).unwrap();
+        }
+        state.station_maps.get(&id).cloned()
+    }
+}
+
+//
+// LruCache
+//
+
+/// A small least recently used cache, the front holds the most recently used entry.
+struct LruCache<K, V> {
+    capacity: usize,
+    entries: VecDeque<(K, V)>,
+}
+
+impl<K: PartialEq, V> LruCache<K, V> {
+    fn new(capacity: usize) -> Self {
+        LruCache {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        self.entries.iter().any(|(k, _)| k == key)
+    }
+
+    fn get(&mut self, key: &K) -> Option<&V> {
+        let index = self.entries.iter().position(|(k, _)| k == key)?;
+        let entry = self.entries.remove(index)?;
+        self.entries.push_front(entry);
+        self.entries.front().map(|(_, v)| v)
+    }
+
+    fn put(&mut self, key: K, value: V) {
+        if let Some(index) = self.entries.iter().position(|(k, _)| *k == key) {
+            self.entries.remove(index);
+        }
+        self.entries.push_front((key, value));
+        self.entries.truncate(self.capacity);
+    }
+}
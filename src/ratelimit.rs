@@ -0,0 +1,52 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+//
+// RateLimiter
+//
+
+/// A token bucket shared by all clones: holds up to `rate` tokens, refills `rate` tokens per
+/// second and every request takes one.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    rate: f64,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Negative while requests wait for tokens they already took.
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    /// A limiter for `rate` requests per second, `rate` has to be positive.
+    pub fn new(rate: f64) -> Self {
+        RateLimiter {
+            rate,
+            bucket: Arc::new(Mutex::new(Bucket {
+                tokens: rate,
+                refilled: Instant::now(),
+            })),
+        }
+    }
+
+    /// Takes a token, sleeps until it is available if the bucket is empty.
+    pub fn acquire(&self) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.rate);
+            bucket.refilled = now;
+            bucket.tokens -= 1.0;
+            if bucket.tokens >= 0.0 {
+                return;
+            }
+            Duration::from_secs_f64(-bucket.tokens / self.rate)
+        };
+        thread::sleep(wait);
+    }
+}
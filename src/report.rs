@@ -0,0 +1,83 @@
+use crate::api::StationMap;
+
+/// Number of routes listed in the report.
+const TOP_ROUTES: usize = 10;
+
+/// Number of stations listed per component.
+const COMPONENT_STATIONS: usize = 5;
+
+//
+// Report
+//
+
+/// A Markdown summary of `station_map`, its components ignore the segment directions. The same
+/// station map always results in the same report, so reports of different years can be diffed.
+pub fn to_markdown(station_map: &StationMap) -> String {
+    let routes = station_map.routes();
+    let components = station_map.components(true);
+
+    let mut lines = vec![
+        format!("# {}", station_map.name),
+        String::new(),
+        "| | |".to_string(),
+        "|---|---:|".to_string(),
+        format!("| Id | {} |", station_map.id),
+    ];
+    if let Some(year) = station_map.year {
+        lines.push(format!("| Timetable year | {} |", year));
+    }
+    lines.extend(vec![
+        format!("| Stations | {} |", station_map.stations.len()),
+        format!("| Segments | {} |", station_map.segments.len()),
+        format!(
+            "| Total length (km) | {:.1} |",
+            station_map.total_length_km()
+        ),
+        format!("| Routes | {} |", routes.len()),
+        format!(
+            "| Isolated stations | {} |",
+            station_map.isolated_stations().len()
+        ),
+        format!("| Connected components | {} |", components.len()),
+        String::new(),
+        "## Top routes".to_string(),
+        String::new(),
+        "| Route | Segments |".to_string(),
+        "|---|---:|".to_string(),
+    ]);
+    for (routenumber, count) in routes.iter().take(TOP_ROUTES) {
+        lines.push(format!("| {} | {} |", routenumber, count));
+    }
+
+    if !station_map.warnings.is_empty() {
+        lines.extend(vec![
+            String::new(),
+            "## Warnings".to_string(),
+            String::new(),
+        ]);
+        for warning in &station_map.warnings {
+            lines.push(format!("* {}", warning));
+        }
+    }
+
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+/// One line per component of `station_map`, largest first: its size and its first stations.
+pub fn components(station_map: &StationMap, undirected: bool) -> String {
+    let mut lines = Vec::new();
+    for component in station_map.components(undirected) {
+        let mut stations: Vec<&str> = component
+            .iter()
+            .take(COMPONENT_STATIONS)
+            .map(|&index| station_map.stations[index].ds100.as_str())
+            .collect();
+        if component.len() > COMPONENT_STATIONS {
+            stations.push("...");
+        }
+        lines.push(format!("{}: {}", component.len(), stations.join(", ")));
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
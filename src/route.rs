@@ -0,0 +1,29 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+//
+// Route
+//
+
+/// Writes the `ds100`s of a route to `path`, one station per line.
+pub fn save(path: &Path, route: &[String]) -> Result<()> {
+    let mut content = route.join("\n");
+    content.push('\n');
+    fs::write(path, content)
+        .with_context(|| format!("Could not write route file '{}'", path.display()))
+}
+
+/// Reads the `ds100`s of a route from `path`, empty lines and lines starting with `#` are
+/// ignored.
+pub fn load(path: &Path) -> Result<Vec<String>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Could not read route file '{}'", path.display()))?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
@@ -0,0 +1,151 @@
+use ordered_float::OrderedFloat;
+
+//
+// KdTree
+//
+
+/// A 2-d tree of points for nearest neighbor queries, built once and never changed.
+pub struct KdTree {
+    points: Vec<(f64, f64)>,
+    /// Indices into `points`. The middle index of every range splits it at its point, along x
+    /// at even depths and along y at odd depths.
+    nodes: Vec<usize>,
+}
+
+impl KdTree {
+    pub fn new(points: Vec<(f64, f64)>) -> Self {
+        let mut nodes: Vec<usize> = (0..points.len()).collect();
+        build(&mut nodes, &points, 0);
+        KdTree { points, nodes }
+    }
+
+    /// The index of the point nearest to `x`, `y` and the squared distance to it. Distances
+    /// along x are multiplied by `scale.0`, along y by `scale.1`.
+    pub fn nearest(&self, x: f64, y: f64, scale: (f64, f64)) -> Option<(usize, f64)> {
+        let mut best = None;
+        self.search(&self.nodes, (x, y), scale, 0, &mut best);
+        best
+    }
+
+    fn search(
+        &self,
+        nodes: &[usize],
+        query: (f64, f64),
+        scale: (f64, f64),
+        depth: usize,
+        best: &mut Option<(usize, f64)>,
+    ) {
+        if nodes.is_empty() {
+            return;
+        }
+        let mid = nodes.len() / 2;
+        let point = self.points[nodes[mid]];
+        let dx = (point.0 - query.0) * scale.0;
+        let dy = (point.1 - query.1) * scale.1;
+        let squared = dx * dx + dy * dy;
+        if best.map_or(true, |(_, best_squared)| squared < best_squared) {
+            *best = Some((nodes[mid], squared));
+        }
+
+        // the query side first, the other side only if it can hold a nearer point
+        let split = if depth % 2 == 0 { dx } else { dy };
+        let (near, far) = if split > 0.0 {
+            (&nodes[..mid], &nodes[mid + 1..])
+        } else {
+            (&nodes[mid + 1..], &nodes[..mid])
+        };
+        self.search(near, query, scale, depth + 1, best);
+        if best.map_or(true, |(_, best_squared)| split * split < best_squared) {
+            self.search(far, query, scale, depth + 1, best);
+        }
+    }
+}
+
+fn build(nodes: &mut [usize], points: &[(f64, f64)], depth: usize) {
+    if nodes.len() <= 1 {
+        return;
+    }
+    let mid = nodes.len() / 2;
+    let coordinate = |index: usize| {
+        let (x, y) = points[index];
+        OrderedFloat(if depth % 2 == 0 { x } else { y })
+    };
+    nodes.select_nth_unstable_by_key(mid, |&index| coordinate(index));
+    let (lower, upper) = nodes.split_at_mut(mid);
+    build(lower, points, depth + 1);
+    build(&mut upper[1..], points, depth + 1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A small linear congruential generator, the points only have to be spread.
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> f64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (self.0 >> 11) as f64 / (1u64 << 53) as f64
+        }
+    }
+
+    fn brute_force(points: &[(f64, f64)], x: f64, y: f64, scale: (f64, f64)) -> Option<f64> {
+        points
+            .iter()
+            .map(|&(px, py)| {
+                let dx = (px - x) * scale.0;
+                let dy = (py - y) * scale.1;
+                dx * dx + dy * dy
+            })
+            .min_by_key(|&squared| OrderedFloat(squared))
+    }
+
+    fn assert_matches_brute_force(points: Vec<(f64, f64)>, random: &mut Random) {
+        let tree = KdTree::new(points.clone());
+        for _ in 0..200 {
+            let (x, y) = (random.next() * 12.0 - 1.0, random.next() * 12.0 - 1.0);
+            let scale = (1.0 + random.next() * 3.0, 1.0 + random.next() * 3.0);
+            let nearest = tree.nearest(x, y, scale);
+            let expected = brute_force(&points, x, y, scale);
+            // ties may pick any of the nearest points, so compare the distances
+            assert_eq!(nearest.map(|(_, squared)| squared), expected);
+            if let Some((index, squared)) = nearest {
+                let dx = (points[index].0 - x) * scale.0;
+                let dy = (points[index].1 - y) * scale.1;
+                assert_eq!(dx * dx + dy * dy, squared);
+            }
+        }
+    }
+
+    #[test]
+    fn nearest_matches_brute_force() {
+        let mut random = Random(7);
+        for &len in &[1, 2, 3, 10, 100, 1000] {
+            let points = (0..len)
+                .map(|_| (random.next() * 10.0, random.next() * 10.0))
+                .collect();
+            assert_matches_brute_force(points, &mut random);
+        }
+    }
+
+    #[test]
+    fn nearest_with_duplicate_points() {
+        let mut random = Random(11);
+        let mut points: Vec<(f64, f64)> = (0..50)
+            .map(|_| ((random.next() * 5.0).round(), (random.next() * 5.0).round()))
+            .collect();
+        points.extend(points.clone());
+        points.extend(vec![(2.0, 2.0); 20]);
+        assert_matches_brute_force(points, &mut random);
+    }
+
+    #[test]
+    fn nearest_in_empty_tree() {
+        let tree = KdTree::new(Vec::new());
+        assert_eq!(tree.nearest(1.0, 2.0, (1.0, 1.0)), None);
+    }
+}
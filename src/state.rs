@@ -0,0 +1,74 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of recently opened infrastructures kept.
+const RECENT_CAPACITY: usize = 10;
+
+//
+// State
+//
+
+/// What tie remembers between runs, stored as json.
+#[derive(Debug, Default)]
+pub struct State {
+    content: Content,
+    path: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+struct Content {
+    /// The most recent first.
+    recent_infrastructures: Vec<u64>,
+}
+
+impl State {
+    /// Loads the state from `path`, a missing or unreadable file starts an empty state. Without
+    /// a path nothing is remembered.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let content = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        State { content, path }
+    }
+
+    /// `<os config dir>/tie/state.json`, next to the config file, if the os has a config dir.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("tie").join("state.json"))
+    }
+
+    /// The ids of the infrastructures last opened from the infrastructure list, the most
+    /// recent first.
+    pub fn recent_infrastructures(&self) -> &[u64] {
+        &self.content.recent_infrastructures
+    }
+
+    /// Moves `id` to the front of the recent infrastructures, the oldest ones are dropped.
+    pub fn push_recent_infrastructure(&mut self, id: u64) {
+        let recent = &mut self.content.recent_infrastructures;
+        recent.retain(|&recent_id| recent_id != id);
+        recent.insert(0, id);
+        recent.truncate(RECENT_CAPACITY);
+    }
+
+    pub fn save(&self) -> Result<()> {
+        match self.path {
+            Some(ref path) => write_state(path, &self.content),
+            None => Ok(()),
+        }
+    }
+}
+
+fn write_state(path: &Path, content: &Content) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Could not create state dir '{}'", dir.display()))?;
+    }
+    fs::write(path, serde_json::to_string(content)?)
+        .with_context(|| format!("Could not write state file '{}'", path.display()))
+}
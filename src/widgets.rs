@@ -1,299 +1,2757 @@
-use anyhow::Result;
-use ordered_float::OrderedFloat;
-use termion::event::Key;
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{anyhow, bail, Context, Result};
+use termion::event::{Key, MouseButton, MouseEvent};
 use tui::buffer::Buffer;
 use tui::layout::{Constraint, Direction, Layout, Rect};
-use tui::style::{Color, Modifier, Style};
-use tui::widgets::canvas::{Canvas, Line, Points};
-use tui::widgets::{Block, Borders, SelectableList, Widget};
+use tui::style::{Color, Style};
+use tui::widgets::canvas::{self, Canvas, Line, Points};
+use tui::widgets::{BarChart, Block, Borders, Paragraph, SelectableList, Text, Widget};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 use crate::api::{
-    read_infrastructure_infos, read_station_map, InfrastrukturInfo, Segment, Station, StationMap,
+    self, calc_extent, fold, DataSource, Extent, InfrastrukturInfo, Segment, Station, StationMap,
+    Stats,
 };
-use tui::backend::Backend;
-use tui::Frame;
+use crate::config::{Config, Panel, Theme};
+use crate::history::History;
+use crate::keymap::{key_name, Command, KeyMap};
+use crate::route;
+use crate::spatial::KdTree;
+
+pub enum TermWidget {
+    InfrastrukturSelection(InfrastrukturSelectionWidget),
+    Map(Box<MapWidget>),
+    Error(ErrorWidget),
+    Loading(LoadingWidget),
+}
+
+impl TermWidget {
+    /// Handles `command` and returns the next widget. A failed action shows an `ErrorWidget`,
+    /// a successful one pushes the current widget onto `stack`.
+    pub fn select_command(
+        mut self,
+        command: Command,
+        source: &DataSource,
+        config: &Config,
+        stack: &mut Vec<TermWidget>,
+    ) -> Self {
+        let action = match self {
+            TermWidget::InfrastrukturSelection(ref mut widget) => {
+                widget.select_command(command, source)
+            }
+            TermWidget::Map(ref mut widget) => match widget.select_command(command, config) {
+                Ok(action) => action,
+                Err(error) => return TermWidget::Error(ErrorWidget::new(error, None, self)),
+            },
+            TermWidget::Error(widget) => {
+                return widget.select_command(command, source, config, stack)
+            }
+            TermWidget::Loading(widget) => return widget.select_command(command),
+        };
+
+        self.run(action, source, config, stack)
+    }
+
+    /// Handles the mouse `event` and returns the next widget, see `select_command`.
+    pub fn select_mouse(
+        mut self,
+        event: MouseEvent,
+        source: &DataSource,
+        config: &Config,
+        stack: &mut Vec<TermWidget>,
+    ) -> Self {
+        let action = match self {
+            TermWidget::InfrastrukturSelection(ref mut widget) => {
+                widget.select_mouse(event, source)
+            }
+            TermWidget::Map(ref mut widget) => widget.select_mouse(event),
+            TermWidget::Error(_) | TermWidget::Loading(_) => None,
+        };
+
+        self.run(action, source, config, stack)
+    }
+
+    fn run(
+        self,
+        action: Option<Action>,
+        source: &DataSource,
+        config: &Config,
+        stack: &mut Vec<TermWidget>,
+    ) -> Self {
+        match action {
+            Some(Action::Back) if !stack.is_empty() => stack.pop().unwrap(),
+            Some(action) => {
+                TermWidget::Loading(LoadingWidget::start(action, source, config, self, 0))
+            }
+            None => self,
+        }
+    }
+
+    /// Shows the result of a finished `LoadingWidget`, like `run` does for a synchronous
+    /// action. Other widgets are returned unchanged.
+    pub fn poll(self, stack: &mut Vec<TermWidget>) -> Self {
+        match self {
+            TermWidget::Loading(widget) => widget.poll(stack),
+            widget => widget,
+        }
+    }
+
+    /// Handles `key` while the widget edits text, returns whether it did. The prompts record
+    /// their queries in `history` and browse it with the arrow keys.
+    pub fn select_key(&mut self, key: Key, source: &DataSource, history: &mut History) -> bool {
+        match *self {
+            TermWidget::InfrastrukturSelection(ref mut widget) if widget.is_filtering() => {
+                widget.select_key(key, source, history);
+                true
+            }
+            TermWidget::Map(ref mut widget) if widget.is_searching() => {
+                widget.select_key(key, history);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn paste(&mut self, text: &str) {
+        match *self {
+            TermWidget::InfrastrukturSelection(ref mut widget) => widget.paste(text),
+            TermWidget::Map(ref mut widget) => widget.paste(text),
+            TermWidget::Error(_) | TermWidget::Loading(_) => {}
+        }
+    }
+
+    /// The id of the selected or shown infrastructure.
+    pub fn infrastructure_id(&self) -> Option<u64> {
+        match *self {
+            TermWidget::InfrastrukturSelection(ref widget) => {
+                widget.selected_value().map(|info| info.id)
+            }
+            TermWidget::Map(ref widget) => Some(widget.station_map.id),
+            TermWidget::Error(_) | TermWidget::Loading(_) => None,
+        }
+    }
+
+    /// The map coordinates under the mouse, see `MapWidget::cursor_position`.
+    pub fn cursor_position(&self, x: u16, y: u16) -> Option<String> {
+        match *self {
+            TermWidget::Map(ref widget) => widget.cursor_position(x, y),
+            _ => None,
+        }
+    }
+
+    /// The shown part of the map.
+    pub fn view(&self) -> Option<Extent> {
+        match *self {
+            TermWidget::Map(ref widget) => Some(widget.view),
+            _ => None,
+        }
+    }
+
+    /// Advances running animations, returns whether the widget has to be redrawn.
+    pub fn tick(&mut self) -> bool {
+        match *self {
+            TermWidget::InfrastrukturSelection(_) => false,
+            TermWidget::Map(ref mut widget) => widget.tick(),
+            TermWidget::Error(ref mut widget) => widget.previous.tick(),
+            TermWidget::Loading(ref mut widget) => widget.tick(),
+        }
+    }
+
+    /// Skips running animations, returns whether there was one.
+    pub fn finish_animation(&mut self) -> bool {
+        match *self {
+            TermWidget::InfrastrukturSelection(_) => false,
+            TermWidget::Map(ref mut widget) => widget.finish_animation(),
+            TermWidget::Error(ref mut widget) => widget.previous.finish_animation(),
+            TermWidget::Loading(_) => false,
+        }
+    }
+
+    /// The commands the footer names, with their labels.
+    fn footer(&self) -> &'static [(&'static [Command], &'static str)] {
+        match *self {
+            TermWidget::InfrastrukturSelection(_) => &[
+                (&[Command::Up, Command::Down], "Auswahl"),
+                (&[Command::Select], "Öffnen"),
+                (&[Command::Filter], "Filtern"),
+                (&[Command::CycleSort], "Sortieren"),
+                (&[Command::OpenPalette], "Befehle"),
+                (&[Command::Quit], "Beenden"),
+            ],
+            TermWidget::Map(_) => &[
+                (&[Command::Up, Command::Down], "Auswahl"),
+                (&[Command::CycleFocus], "Bereich"),
+                (&[Command::Filter], "Suchen"),
+                (&[Command::ZoomIn, Command::ZoomOut], "Zoom"),
+                (&[Command::Back], "Zurück"),
+                (&[Command::OpenPalette], "Befehle"),
+                (&[Command::Quit], "Beenden"),
+            ],
+            TermWidget::Error(_) => &[
+                (&[Command::Retry], "Erneut versuchen"),
+                (&[Command::Back], "Zurück"),
+                (&[Command::Quit], "Beenden"),
+            ],
+            TermWidget::Loading(_) => &[
+                (&[Command::Back], "Abbrechen"),
+                (&[Command::Quit], "Beenden"),
+            ],
+        }
+    }
+
+    pub fn set_config(&mut self, config: &Config) {
+        match *self {
+            TermWidget::InfrastrukturSelection(ref mut widget) => widget.set_config(config),
+            TermWidget::Map(ref mut widget) => widget.set_config(config),
+            TermWidget::Error(ref mut widget) => widget.previous.set_config(config),
+            TermWidget::Loading(ref mut widget) => widget.previous.set_config(config),
+        }
+    }
+}
+
+//
+// Action
+//
+
+/// An action that leads to another widget.
+#[derive(Debug, Clone)]
+pub enum Action {
+    FetchInfos,
+    /// Fetches the station maps of the ids and shows them merged into one map.
+    FetchMap(Vec<u64>),
+    /// Shows the station map read from a file.
+    OpenFile(PathBuf),
+    /// Returns to the previous widget, or to the infrastructure selection if there is none.
+    Back,
+}
+
+impl Action {
+    pub fn run(&self, source: &DataSource, config: &Config) -> Result<TermWidget> {
+        match *self {
+            Action::FetchInfos | Action::Back => Ok(TermWidget::InfrastrukturSelection(
+                InfrastrukturSelectionWidget::from_source(source, config)?,
+            )),
+            Action::FetchMap(ref ids) => Ok(TermWidget::Map(Box::new(MapWidget::from_source(
+                source, ids, config,
+            )?))),
+            Action::OpenFile(ref path) => {
+                Ok(TermWidget::Map(Box::new(MapWidget::from_station_map(
+                    api::read_station_map_from_file(path, config.lenient)?,
+                    config,
+                )?)))
+            }
+        }
+    }
+}
+
+impl Widget for TermWidget {
+    fn draw(&mut self, area: Rect, buf: &mut Buffer) {
+        match *self {
+            TermWidget::InfrastrukturSelection(ref mut widget) => widget.draw(area, buf),
+            TermWidget::Map(ref mut widget) => widget.draw(area, buf),
+            TermWidget::Error(ref mut widget) => widget.draw(area, buf),
+            TermWidget::Loading(ref mut widget) => widget.draw(area, buf),
+        }
+    }
+}
+
+//
+// InfrastrukturSelectionWidget
+//
+
+pub struct InfrastrukturSelectionWidget {
+    values: Vec<InfrastrukturInfo>,
+    items: Vec<String>,
+    visible: Vec<usize>,     // indices into values
+    selected: Option<usize>, // index into visible
+    sorting: Sorting,
+    /// Only infrastructures whose name or id contain the query are visible.
+    query: String,
+    /// Typed characters are appended to the query.
+    filtering: bool,
+    /// The timetable year the values are limited to.
+    year: Option<u32>,
+    /// The ids of the recently opened infrastructures, the most recent first.
+    recent: Vec<u64>,
+    /// The first `recent_count` visible values are the recent ones, shown above the others.
+    recent_count: usize,
+    highlight: Style,
+    highlight_symbol: &'static str,
+    theme: Theme,
+    /// Moving past the last item selects the first one and vice versa.
+    wrap: bool,
+    area: Rect,
+}
+
+impl InfrastrukturSelectionWidget {
+    pub fn new(mut values: Vec<InfrastrukturInfo>, config: &Config) -> Self {
+        values.sort_by(InfrastrukturInfo::cmp_recency);
+        let items = infrastructure_items(&values);
+        let visible: Vec<usize> = (0..values.len()).collect();
+
+        let selected = if values.is_empty() {
+            None
+        } else {
+            Some(0usize)
+        };
+
+        InfrastrukturSelectionWidget {
+            values,
+            items,
+            visible,
+            selected,
+            sorting: Sorting::default(),
+            query: String::new(),
+            filtering: false,
+            year: None,
+            recent: Vec::new(),
+            recent_count: 0,
+            highlight: config.highlight_style(),
+            highlight_symbol: config.highlight_symbol(),
+            theme: config.theme,
+            wrap: config.wrap,
+            area: Rect::default(),
+        }
+    }
+
+    /// Reads the infrastructures of the timetable year `config.year`, or of all years.
+    pub fn from_source(source: &DataSource, config: &Config) -> Result<Self> {
+        let mut values = source.read_infrastructure_infos()?;
+        if let Some(year) = config.year {
+            values.retain(|info| info.fahrplanjahr == year);
+        }
+        let mut widget = Self::new(values, config);
+        widget.year = config.year;
+        widget.recent = config.recent_infrastructures.clone();
+        widget.filter(None);
+        widget.prefetch_adjacent(source);
+        Ok(widget)
+    }
+
+    pub fn set_config(&mut self, config: &Config) {
+        self.highlight = config.highlight_style();
+        self.highlight_symbol = config.highlight_symbol();
+        self.theme = config.theme;
+        self.wrap = config.wrap;
+        if self.recent != config.recent_infrastructures {
+            let selected_id = self.selected_value().map(|info| info.id);
+            self.recent = config.recent_infrastructures.clone();
+            self.filter(selected_id);
+        }
+    }
+
+    /// Whether keys edit the filter query instead of running commands.
+    pub fn is_filtering(&self) -> bool {
+        self.filtering
+    }
+
+    fn up(&mut self) {
+        self.selected = if self.wrap {
+            up_wrap(&self.visible, self.selected)
+        } else {
+            up(&self.visible, self.selected)
+        };
+    }
+
+    fn down(&mut self) {
+        self.selected = if self.wrap {
+            down_wrap(&self.visible, self.selected)
+        } else {
+            down(&self.visible, self.selected)
+        };
+    }
+
+    /// Sorts the values with `sorting`, the selected value stays selected.
+    fn sort(&mut self, sorting: Sorting) {
+        let selected_id = self.selected_value().map(|info| info.id);
+        match sorting.key {
+            SortKey::Default => self.values.sort_by(InfrastrukturInfo::cmp_recency),
+            SortKey::Name => self
+                .values
+                .sort_by(|a, b| a.anzeigename.cmp(&b.anzeigename).then(a.id.cmp(&b.id))),
+        }
+        if sorting.reverse {
+            self.values.reverse();
+        }
+        self.items = infrastructure_items(&self.values);
+        self.sorting = sorting;
+        self.filter(selected_id);
+    }
+
+    /// Shows the values matching the query, without a query the recent values come first. The
+    /// value `selected_id` stays selected if it is still visible, otherwise the selection is
+    /// clamped to the visible values.
+    fn filter(&mut self, selected_id: Option<u64>) {
+        let query = fold(&self.query);
+        let values = &self.values;
+        // recent infrastructures that are gone or of another year are left out
+        let recent: Vec<usize> = if query.is_empty() {
+            self.recent
+                .iter()
+                .filter_map(|&id| values.iter().position(|info| info.id == id))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        self.recent_count = recent.len();
+        self.visible = recent
+            .into_iter()
+            .chain((0..values.len()).filter(|&index| {
+                let info = &values[index];
+                fold(&info.anzeigename).contains(&query) || info.id.to_string().contains(&query)
+            }))
+            .collect();
+        let position = self.selected.unwrap_or(0);
+        self.selected = selected_id
+            .and_then(|id| self.visible.iter().position(|&i| values[i].id == id))
+            .or_else(|| match self.visible.len() {
+                0 => None,
+                len => Some(position.min(len - 1)),
+            });
+    }
+
+    fn set_query(&mut self, query: String) {
+        let selected_id = self.selected_value().map(|info| info.id);
+        self.query = query;
+        self.filter(selected_id);
+    }
+
+    /// Edits the query while filtering: `Backspace` deletes the last character, `Esc` clears
+    /// the query and `Enter` keeps it, both end filtering. The arrow keys browse `history`.
+    pub fn select_key(&mut self, key: Key, source: &DataSource, history: &mut History) {
+        match key {
+            Key::Esc => {
+                history.reset();
+                self.filtering = false;
+                self.set_query(String::new());
+            }
+            Key::Char('\n') => {
+                history.push(&self.query);
+                self.filtering = false;
+            }
+            Key::Up | Key::Ctrl('p') => {
+                if let Some(query) = history.prev() {
+                    self.set_query(query.to_string());
+                }
+            }
+            Key::Down | Key::Ctrl('n') => {
+                self.set_query(history.next().unwrap_or_default().to_string())
+            }
+            Key::Backspace => {
+                let mut query = self.query.clone();
+                query.pop();
+                self.set_query(query);
+            }
+            Key::Char(c) if !c.is_control() => self.set_query(format!("{}{}", self.query, c)),
+            _ => return,
+        }
+        self.prefetch_adjacent(source);
+    }
+
+    pub fn paste(&mut self, text: &str) {
+        if self.filtering {
+            self.set_query(format!("{}{}", self.query, text));
+        }
+    }
+
+    fn selected_value(&self) -> Option<&InfrastrukturInfo> {
+        self.selected
+            .and_then(|index| self.visible.get(index))
+            .map(|&index| &self.values[index])
+    }
+
+    /// Prefetches the station maps of the selection and the values above and below it, so Enter
+    /// opens them instantly.
+    fn prefetch_adjacent(&self, source: &DataSource) {
+        if let Some(index) = self.selected {
+            let ids: Vec<u64> = [Some(index), index.checked_sub(1), Some(index + 1)]
+                .iter()
+                .filter_map(|adjacent| adjacent.and_then(|i| self.visible.get(i)))
+                .map(|&i| self.values[i].id)
+                .collect();
+            source.prefetch_station_maps(&ids);
+        }
+    }
+
+    pub fn select_command(&mut self, command: Command, source: &DataSource) -> Option<Action> {
+        match command {
+            Command::Up => {
+                self.up();
+                self.prefetch_adjacent(source);
+            }
+            Command::Down => {
+                self.down();
+                self.prefetch_adjacent(source);
+            }
+            Command::Select => {
+                return self
+                    .selected_value()
+                    .map(|info| Action::FetchMap(vec![info.id]));
+            }
+            Command::PageUp | Command::PageDown | Command::First | Command::Last => {
+                self.selected = jump(command, &self.visible, self.selected, self.area);
+                self.prefetch_adjacent(source);
+            }
+            Command::CycleSort => self.sort(self.sorting.next_key()),
+            Command::ReverseSort => self.sort(self.sorting.reversed()),
+            Command::Filter => self.filtering = true,
+            Command::Back if !self.query.is_empty() => self.set_query(String::new()),
+            _ => {}
+        }
+        None
+    }
+
+    pub fn select_mouse(&mut self, event: MouseEvent, source: &DataSource) -> Option<Action> {
+        match event {
+            MouseEvent::Press(MouseButton::WheelUp, x, y) if contains(self.area, x, y) => {
+                self.up();
+                self.prefetch_adjacent(source);
+            }
+            MouseEvent::Press(MouseButton::WheelDown, x, y) if contains(self.area, x, y) => {
+                self.down();
+                self.prefetch_adjacent(source);
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+impl Widget for InfrastrukturSelectionWidget {
+    fn draw(&mut self, area: Rect, buf: &mut Buffer) {
+        let rect = centered_rect(40, 40, area);
+        self.area = rect;
+        let mut title = format!("Infrastrukturen{}", self.sorting.title_suffix());
+        if self.filtering || !self.query.is_empty() {
+            title = format!("{} /{}", title, self.query);
+        }
+        if let Some(year) = self.year {
+            title = format!("{} - {}", title, year);
+        }
+        let width = list_width(rect, self.highlight_symbol);
+        let mut items: Vec<String> = self
+            .visible
+            .iter()
+            .map(|&index| truncate_to_width(&self.items[index], width))
+            .collect();
+        let recent_items: Vec<String> = items.drain(..self.recent_count).collect();
+        // the selection continues from the recent list into the full list
+        let (recent_selected, selected) = match self.selected {
+            Some(selected) if selected < self.recent_count => (Some(selected), None),
+            selected => (None, selected.map(|selected| selected - self.recent_count)),
+        };
+        let rect = if recent_items.is_empty() {
+            rect
+        } else {
+            let recent_height = (recent_items.len() as u16 + 2).min(rect.height / 2);
+            SelectableList::default()
+                .block(Block::default().title(RECENT_TITLE).borders(Borders::ALL))
+                .items(&recent_items[..])
+                .style(Style::default().fg(self.theme.list))
+                .highlight_style(self.highlight)
+                .highlight_symbol(self.highlight_symbol)
+                .select(recent_selected)
+                .draw(
+                    Rect {
+                        height: recent_height,
+                        ..rect
+                    },
+                    buf,
+                );
+            Rect {
+                y: rect.y + recent_height,
+                height: rect.height - recent_height,
+                ..rect
+            }
+        };
+        if items.is_empty() {
+            let message = match self.year {
+                _ if !self.values.is_empty() => "Keine Treffer".to_string(),
+                Some(year) => format!("Keine Infrastrukturen im Fahrplanjahr {}", year),
+                None => "Keine Infrastrukturen".to_string(),
+            };
+            items.push(truncate_to_width(&message, width));
+        }
+
+        SelectableList::default()
+            .block(Block::default().title(&title).borders(Borders::ALL))
+            .items(&items[..])
+            .style(Style::default().fg(self.theme.list))
+            .highlight_style(self.highlight)
+            .highlight_symbol(self.highlight_symbol)
+            .select(selected)
+            .draw(rect, buf);
+    }
+}
+
+const RECENT_TITLE: &str = "Zuletzt geöffnet";
+
+/// The id, name and validity of each infrastructure.
+fn infrastructure_items(values: &[InfrastrukturInfo]) -> Vec<String> {
+    values
+        .iter()
+        .map(|info| {
+            format!(
+                "{}: {} ({} - {})",
+                info.id, info.anzeigename, info.gueltig_von, info.gueltig_bis
+            )
+        })
+        .collect()
+}
+
+//
+// Sorting
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum SortKey {
+    /// The order of the api, stations and segments keep it. Infrastructures are ordered by
+    /// recency, see `InfrastrukturInfo::cmp_recency`.
+    #[default]
+    Default,
+    Name,
+}
+
+/// The sort key and direction of a list.
+#[derive(Debug, Clone, Copy, Default)]
+struct Sorting {
+    key: SortKey,
+    reverse: bool,
+}
+
+impl Sorting {
+    /// The next sort key in the same direction.
+    fn next_key(self) -> Self {
+        let key = match self.key {
+            SortKey::Default => SortKey::Name,
+            SortKey::Name => SortKey::Default,
+        };
+        Sorting { key, ..self }
+    }
+
+    fn reversed(self) -> Self {
+        Sorting {
+            reverse: !self.reverse,
+            ..self
+        }
+    }
+
+    /// E.g. ` (Name ↓)`, empty for the unreversed default order.
+    fn title_suffix(self) -> String {
+        let arrow = if self.reverse { '↓' } else { '↑' };
+        match (self.key, self.reverse) {
+            (SortKey::Default, false) => String::new(),
+            (SortKey::Default, true) => format!(" ({})", arrow),
+            (SortKey::Name, _) => format!(" (Name {})", arrow),
+        }
+    }
+}
+
+//
+// Screen
+//
+
+/// The current widget with the command palette (if open) on top. While the api is
+/// unreachable a banner names the age of the cached data shown instead.
+pub struct Screen<'a> {
+    pub widget: &'a mut TermWidget,
+    pub palette: Option<&'a mut CommandPalette>,
+    pub offline_since: Option<SystemTime>,
+    /// A message shown on the bottom row until the next key.
+    pub status: Option<&'a str>,
+    /// Shown in the bottom right corner while requests are in flight.
+    pub spinner: Option<char>,
+    /// The map coordinates under the mouse, shown right aligned on the bottom row.
+    pub cursor: Option<&'a str>,
+    /// The key bindings named in the footer and the help.
+    pub keys: &'a KeyMap,
+    /// The scroll offset of the help, `None` while it is closed.
+    pub help: Option<u16>,
+    pub theme: &'a Theme,
+}
+
+impl<'a> Widget for Screen<'a> {
+    fn draw(&mut self, area: Rect, buf: &mut Buffer) {
+        // the bottom row is the footer
+        if area.height > 1 {
+            let widget_area = Rect::new(area.x, area.y, area.width, area.height - 1);
+            self.widget.draw(widget_area, buf);
+            buf.set_stringn(
+                area.x,
+                area.bottom() - 1,
+                footer_text(self.widget, self.keys),
+                usize::from(area.width),
+                Style::default().fg(Color::Gray),
+            );
+        } else {
+            self.widget.draw(area, buf);
+        }
+        if let Some(ref mut palette) = self.palette {
+            palette.draw(area, buf);
+        }
+        if let Some(scroll) = self.help {
+            draw_help(self.keys, scroll, area, buf);
+        }
+
+        if let Some(since) = self.offline_since {
+            let age = SystemTime::now().duration_since(since).unwrap_or_default();
+            let banner = format!(
+                " Offline - zwischengespeicherte Daten von vor {} ",
+                format_age(age)
+            );
+            let rect = Rect::new(area.x, area.y, area.width, area.height.min(1));
+            clear(rect, buf);
+            buf.set_stringn(
+                rect.x,
+                rect.y,
+                format!("{:^width$}", banner, width = usize::from(rect.width)),
+                usize::from(rect.width),
+                Style::default().fg(Color::White).bg(Color::Red),
+            );
+        }
+
+        if let Some(status) = self.status {
+            let y = area.y + area.height.saturating_sub(1);
+            let rect = Rect::new(area.x, y, area.width, area.height.min(1));
+            clear(rect, buf);
+            buf.set_stringn(
+                rect.x,
+                rect.y,
+                status,
+                usize::from(rect.width),
+                Style::default().fg(self.theme.status),
+            );
+        }
+
+        if let Some(cursor) = self.cursor {
+            // leaves the column of the spinner free
+            let width = cursor.width() as u16 + 1;
+            if area.width > width && area.height > 0 {
+                buf.set_string(
+                    area.right() - width - 1,
+                    area.bottom() - 1,
+                    format!(" {}", cursor),
+                    Style::default().fg(self.theme.status),
+                );
+            }
+        }
+
+        if let Some(spinner) = self.spinner {
+            if area.width > 0 && area.height > 0 {
+                buf.get_mut(area.right() - 1, area.bottom() - 1)
+                    .set_char(spinner)
+                    .set_style(Style::default().fg(self.theme.status));
+            }
+        }
+    }
+}
+
+/// E.g. `↑↓ Auswahl  Enter Öffnen  q Beenden`, with the first key bound to every command.
+/// Commands without a key are left out.
+fn footer_text(widget: &TermWidget, keys: &KeyMap) -> String {
+    widget
+        .footer()
+        .iter()
+        .filter_map(|&(commands, label)| {
+            let bound: Vec<Key> = commands
+                .iter()
+                .filter_map(|&command| keys.keys(command).first().cloned())
+                .collect();
+            if bound.is_empty() {
+                return None;
+            }
+            let arrows = bound
+                .iter()
+                .all(|key| matches!(key, Key::Up | Key::Down | Key::Left | Key::Right));
+            let names: Vec<String> = bound.into_iter().map(key_label).collect();
+            Some(format!(
+                "{} {}",
+                names.join(if arrows { "" } else { "/" }),
+                label
+            ))
+        })
+        .collect::<Vec<String>>()
+        .join("  ")
+}
+
+/// The name of `key` in the footer, arrow keys are shown as arrows.
+fn key_label(key: Key) -> String {
+    match key {
+        Key::Up => "↑".to_string(),
+        Key::Down => "↓".to_string(),
+        Key::Left => "←".to_string(),
+        Key::Right => "→".to_string(),
+        key => key_name(key),
+    }
+}
+
+/// Dims `area` and lists all commands with their keys and descriptions in a box on top.
+fn draw_help(keys: &KeyMap, scroll: u16, area: Rect, buf: &mut Buffer) {
+    for y in area.top()..area.bottom() {
+        for x in area.left()..area.right() {
+            buf.get_mut(x, y)
+                .set_fg(Color::DarkGray)
+                .set_bg(Color::Reset);
+        }
+    }
+
+    let rect = centered_rect(70, 80, area);
+    clear(rect, buf);
+    let lines: Vec<String> = Command::ALL
+        .iter()
+        .map(|&command| {
+            let names: Vec<String> = keys.keys(command).into_iter().map(key_label).collect();
+            format!(
+                "{:<12} {:<24} {}\n",
+                names.join(" "),
+                command.name(),
+                command.description()
+            )
+        })
+        .collect();
+    let text: Vec<Text> = lines.iter().map(Text::raw).collect();
+    Paragraph::new(text.iter())
+        .block(
+            Block::default()
+                .title("Hilfe (Esc: Schließen)")
+                .borders(Borders::ALL),
+        )
+        .scroll(scroll)
+        .draw(rect, buf);
+}
+
+/// E.g. `5 Minuten`, `1 Stunde` or `3 Tagen`.
+fn format_age(age: Duration) -> String {
+    let minutes = age.as_secs() / 60;
+    let (value, one, many) = match minutes {
+        0..=59 => (minutes, "Minute", "Minuten"),
+        60..=1439 => (minutes / 60, "Stunde", "Stunden"),
+        _ => (minutes / 1440, "Tag", "Tagen"),
+    };
+    format!("{} {}", value, if value == 1 { one } else { many })
+}
+
+//
+// CommandPalette
+//
+
+/// The result of a key pressed in the command palette.
+pub enum PaletteInput {
+    Editing,
+    Cancel,
+    Execute(Command),
+}
+
+/// Lists all commands filtered by the typed query.
+pub struct CommandPalette {
+    query: String,
+    matches: Vec<Command>,
+    selected: Option<usize>,
+    highlight: Style,
+    highlight_symbol: &'static str,
+    theme: Theme,
+}
+
+/// Maximum number of commands shown above the prompt.
+const PALETTE_HEIGHT: u16 = 10;
+
+impl CommandPalette {
+    pub fn new(config: &Config) -> Self {
+        let mut palette = CommandPalette {
+            query: String::new(),
+            matches: Vec::new(),
+            selected: None,
+            highlight: config.highlight_style(),
+            highlight_symbol: config.highlight_symbol(),
+            theme: config.theme,
+        };
+        palette.update_matches();
+        palette
+    }
+
+    /// Handles `key`, `Ctrl-p` and `Ctrl-n` browse the `history` of queries.
+    pub fn select_key(&mut self, key: Key, history: &mut History) -> PaletteInput {
+        match key {
+            Key::Esc => {
+                history.reset();
+                return PaletteInput::Cancel;
+            }
+            Key::Char('\n') => {
+                history.push(&self.query);
+                return match self.selected.and_then(|index| self.matches.get(index)) {
+                    Some(&command) => PaletteInput::Execute(command),
+                    None => PaletteInput::Cancel,
+                };
+            }
+            Key::Up => self.selected = up(&self.matches, self.selected),
+            Key::Down => self.selected = down(&self.matches, self.selected),
+            Key::Ctrl('p') => {
+                if let Some(query) = history.prev() {
+                    self.query = query.to_string();
+                    self.update_matches();
+                }
+            }
+            Key::Ctrl('n') => {
+                self.query = history.next().unwrap_or_default().to_string();
+                self.update_matches();
+            }
+            Key::Backspace => {
+                self.query.pop();
+                self.update_matches();
+            }
+            Key::Char(c) if !c.is_control() => {
+                self.query.push(c);
+                self.update_matches();
+            }
+            _ => {}
+        }
+        PaletteInput::Editing
+    }
+
+    pub fn paste(&mut self, text: &str) {
+        self.query.push_str(text);
+        self.update_matches();
+    }
+
+    fn update_matches(&mut self) {
+        let query = fold(&self.query);
+        self.matches = Command::ALL
+            .iter()
+            .cloned()
+            .filter(|command| {
+                command.name().contains(&query) || fold(command.description()).contains(&query)
+            })
+            .collect();
+        self.selected = if self.matches.is_empty() {
+            None
+        } else {
+            Some(0)
+        };
+    }
+}
+
+impl Widget for CommandPalette {
+    fn draw(&mut self, area: Rect, buf: &mut Buffer) {
+        if area.height < 3 {
+            return;
+        }
+
+        let prompt_rect = Rect::new(area.x, area.bottom() - 1, area.width, 1);
+        let list_height = (self.matches.len() as u16 + 2)
+            .min(PALETTE_HEIGHT + 2)
+            .min(area.height - 1);
+        let list_rect = Rect::new(area.x, prompt_rect.y - list_height, area.width, list_height);
+
+        clear(list_rect.union(prompt_rect), buf);
+
+        let width = list_width(list_rect, self.highlight_symbol);
+        let items: Vec<String> = self
+            .matches
+            .iter()
+            .map(|command| {
+                let item = format!("{:<24} {}", command.name(), command.description());
+                truncate_to_width(&item, width)
+            })
+            .collect();
+        SelectableList::default()
+            .block(Block::default().title("Befehle").borders(Borders::ALL))
+            .items(&items[..])
+            .style(Style::default().fg(self.theme.list))
+            .highlight_style(self.highlight)
+            .highlight_symbol(self.highlight_symbol)
+            .select(self.selected)
+            .draw(list_rect, buf);
+
+        buf.set_stringn(
+            prompt_rect.x,
+            prompt_rect.y,
+            format!(":{}", self.query),
+            prompt_rect.width as usize,
+            Style::default(),
+        );
+    }
+}
+
+//
+// Ascii
+//
+
+/// Draws the wrapped widget with ASCII characters only, for terminals without unicode support.
+pub struct Ascii<'a, W: Widget>(pub &'a mut W);
+
+impl<'a, W: Widget> Widget for Ascii<'a, W> {
+    fn draw(&mut self, area: Rect, buf: &mut Buffer) {
+        self.0.draw(area, buf);
+
+        for y in area.top()..area.bottom() {
+            for x in area.left()..area.right() {
+                let cell = buf.get_mut(x, y);
+                if let Some(ascii) = cell.symbol.chars().next().and_then(to_ascii) {
+                    cell.set_char(ascii);
+                }
+            }
+        }
+    }
+}
+
+/// Draws the wrapped widget without colors and text styles.
+pub struct NoColor<'a, W: Widget>(pub &'a mut W);
+
+impl<'a, W: Widget> Widget for NoColor<'a, W> {
+    fn draw(&mut self, area: Rect, buf: &mut Buffer) {
+        self.0.draw(area, buf);
+
+        for y in area.top()..area.bottom() {
+            for x in area.left()..area.right() {
+                buf.get_mut(x, y).set_style(Style::default());
+            }
+        }
+    }
+}
+
+/// Replaces box drawing (borders) and braille (canvas) characters.
+fn to_ascii(c: char) -> Option<char> {
+    match c {
+        '─' | '━' | '═' => Some('-'),
+        '│' | '┃' | '║' => Some('|'),
+        '┌' | '┐' | '└' | '┘' | '├' | '┤' | '┬' | '┴' | '┼' => Some('+'),
+        '↑' => Some('^'),
+        '←' => Some('<'),
+        '→' => Some('>'),
+        '…' => Some('~'),
+        '≈' => Some('~'),
+        '↓' => Some('v'),
+        '\u{2800}' => Some(' '),
+        '\u{2801}'..='\u{28ff}' => Some('.'),
+        _ => None,
+    }
+}
+
+/// The columns available for an item of a bordered `SelectableList` in `area`, the list
+/// indents items by one column and the width of the `highlight_symbol`.
+fn list_width(area: Rect, highlight_symbol: &str) -> usize {
+    let inner = Block::default().borders(Borders::ALL).inner(area);
+    usize::from(inner.width.saturating_sub(1)).saturating_sub(highlight_symbol.width())
+}
+
+/// Truncates `s` to at most `width` terminal columns, a truncated string ends with an ellipsis.
+fn truncate_to_width(s: &str, width: usize) -> String {
+    if s.width() <= width {
+        return s.to_string();
+    }
+    if width == 0 {
+        return String::new();
+    }
+
+    let mut truncated = String::new();
+    let mut used = 0;
+    for c in s.chars() {
+        let char_width = c.width().unwrap_or(0);
+        if used + char_width > width - 1 {
+            break;
+        }
+        truncated.push(c);
+        used += char_width;
+    }
+    truncated.push('…');
+    truncated
+}
+
+/// Whether the one-based mouse position `x`, `y` lies in `area`.
+fn contains(area: Rect, x: u16, y: u16) -> bool {
+    let (x, y) = (x.saturating_sub(1), y.saturating_sub(1));
+    x >= area.left() && x < area.right() && y >= area.top() && y < area.bottom()
+}
+
+/// Resets the cells of `rect`, so a popup drawn there hides the widget below.
+fn clear(rect: Rect, buf: &mut Buffer) {
+    for y in rect.top()..rect.bottom() {
+        for x in rect.left()..rect.right() {
+            buf.get_mut(x, y).reset();
+        }
+    }
+}
+
+/// A rect of `percent_x` and `percent_y` of `area`, centered in `area`.
+fn centered_rect(percent_x: u16, percent_y: u16, area: Rect) -> Rect {
+    let margin_x = (100 - percent_x) / 2;
+    let rect = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(vec![
+            Constraint::Percentage(margin_x),
+            Constraint::Percentage(percent_x),
+            Constraint::Percentage(margin_x),
+        ])
+        .split(area)[1];
+
+    let margin_y = (100 - percent_y) / 2;
+    Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(vec![
+            Constraint::Percentage(margin_y),
+            Constraint::Percentage(percent_y),
+            Constraint::Percentage(margin_y),
+        ])
+        .split(rect)[1]
+}
+
+//
+// MapWidget
+//
+
+const STATIONS_TITLE: &str = "Betriebsstellen";
+
+/// Fraction of the view the arrow keys pan the focused map by.
+const PAN_STEP: f64 = 0.1;
+
+/// Labels are drawn if the view shows fewer stations.
+const MAX_LABELS: usize = 50;
+
+/// Height of a terminal cell per width.
+const CELL_ASPECT: f64 = 2.0;
+
+/// Factor a zoom step shrinks or grows the view by.
+const ZOOM_STEP: f64 = 1.5;
+
+/// Margin around the stations a fitted view shows, as a fraction of its size.
+const FIT_MARGIN: f64 = 0.1;
+
+const DOUBLE_CLICK_INTERVAL: Duration = Duration::from_millis(300);
+
+/// Maximum distance in cells between a double click and the station it selects.
+const DOUBLE_CLICK_RADIUS: f64 = 2.0;
+
+#[derive(PartialEq)]
+enum WidgetSelection {
+    Stations,
+    Segments,
+    Map,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ElectrificationFilter {
+    All,
+    Electrified,
+    NotElectrified,
+}
+
+impl ElectrificationFilter {
+    fn next(self) -> Self {
+        match self {
+            ElectrificationFilter::All => ElectrificationFilter::Electrified,
+            ElectrificationFilter::Electrified => ElectrificationFilter::NotElectrified,
+            ElectrificationFilter::NotElectrified => ElectrificationFilter::All,
+        }
+    }
+
+    /// Segments with unknown electrification only match `All`.
+    fn matches(self, segment: &Segment) -> bool {
+        match self {
+            ElectrificationFilter::All => true,
+            ElectrificationFilter::Electrified => segment.electrified == Some(true),
+            ElectrificationFilter::NotElectrified => segment.electrified == Some(false),
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            ElectrificationFilter::All => "Streckensegmente",
+            ElectrificationFilter::Electrified => "Streckensegmente (elektrifiziert)",
+            ElectrificationFilter::NotElectrified => "Streckensegmente (nicht elektrifiziert)",
+        }
+    }
+}
+
+pub struct MapWidget {
+    station_map: StationMap,
+    coordinates: Vec<(f64, f64)>,
+    /// The station coordinates for nearest station queries.
+    station_tree: KdTree,
+    /// The indices of the segments starting or ending at a station, by station index.
+    incident_segments: Vec<Vec<usize>>,
+    /// The coordinates of the stations without segments.
+    isolated: Vec<(f64, f64)>,
+    view: Extent,
+    /// The view a map opens with, `ResetZoom` returns to it.
+    home: Extent,
+
+    stations_widget: ListSelectionWidget,
+    segments_widget: ListSelectionWidget,
+
+    widget_selection: WidgetSelection,
+    electrification_filter: ElectrificationFilter,
+    /// Only segments whose route number starts with the query are listed and drawn.
+    route_query: String,
+    /// Typed characters edit the `route_query`.
+    route_filtering: bool,
+    route: Vec<usize>,
+    animation: Option<Animation>,
+    histogram: Option<Vec<(String, u64)>>,
+    neighbors: Option<NeighborsPopup>,
+    /// The statistics popup, open while set.
+    stats: Option<Stats>,
+    show_stations: bool,
+    show_segments: bool,
+    cluster_cells: Option<u16>,
+    /// Draws larger y coordinates further down, for data whose y axis points south.
+    invert_y: bool,
+    /// Draws an arrowhead on every segment, not only on the selected one.
+    arrows: bool,
+    theme: Theme,
+    /// Segments connect their stations in both directions, see `StationMap::neighbors`.
+    undirected: bool,
+    /// Set while the station search is open.
+    search: Option<Search>,
+    /// Draws the ds100 next to the stations when the view shows few of them.
+    labels: bool,
+    /// The ds100 of the bookmarked stations, kept for the session.
+    bookmarks: HashSet<String>,
+
+    map_area: Rect,
+    drag_start: Option<(u16, u16)>,
+    last_click: Option<(Instant, u16, u16)>,
+}
+
+impl MapWidget {
+    pub fn new(station_map: StationMap, config: &Config) -> Self {
+        let station_names: Vec<String> = station_map
+            .stations
+            .iter()
+            .map(|station| format!("{} ({})", station.ds100, station.longname))
+            .collect();
+
+        let stations_widget: ListSelectionWidget =
+            ListSelectionWidget::new(STATIONS_TITLE.to_string(), station_names, config);
+
+        let segment_names: Vec<String> = station_map.segments.iter().map(segment_name).collect();
+
+        let segments_widget: ListSelectionWidget = ListSelectionWidget::new(
+            ElectrificationFilter::All.title().to_string(),
+            segment_names,
+            config,
+        );
+
+        let coordinates = station_map.coordinates();
+        let view = station_map.extent();
+        let incident_segments = incident_segments(&station_map);
+        let isolated = station_map
+            .isolated_stations()
+            .iter()
+            .map(|station| station.coord)
+            .collect();
+
+        MapWidget {
+            station_map,
+            station_tree: KdTree::new(coordinates.clone()),
+            coordinates,
+            incident_segments,
+            isolated,
+            view,
+            home: view,
+            stations_widget,
+            segments_widget,
+            widget_selection: match config.default_panel {
+                Panel::Stations => WidgetSelection::Stations,
+                Panel::Segments => WidgetSelection::Segments,
+                Panel::Map => WidgetSelection::Map,
+            },
+            electrification_filter: ElectrificationFilter::All,
+            route_query: String::new(),
+            route_filtering: false,
+            route: Vec::new(),
+            animation: None,
+            histogram: None,
+            neighbors: None,
+            stats: None,
+            show_stations: true,
+            show_segments: true,
+            cluster_cells: config.cluster_cells,
+            invert_y: config.flip_y,
+            arrows: config.arrows,
+            theme: config.theme,
+            undirected: !config.directed,
+            search: None,
+            labels: false,
+            bookmarks: HashSet::new(),
+            map_area: Rect::default(),
+            drag_start: None,
+            last_click: None,
+        }
+    }
+
+    /// Reads the station maps `ids` merged into one map, see `from_station_map`.
+    pub fn from_source(source: &DataSource, ids: &[u64], config: &Config) -> Result<Self> {
+        Self::from_station_map(source.read_merged_station_map(ids)?, config)
+    }
+
+    /// Shows `station_map` limited to `config.bbox`, with the route from `config.route_file`,
+    /// if it exists.
+    pub fn from_station_map(station_map: StationMap, config: &Config) -> Result<Self> {
+        let mut widget = match config.bbox {
+            Some(bbox) => {
+                let station_map = station_map.filter_bbox(bbox);
+                if station_map.stations.is_empty() {
+                    bail!("No stations inside the bounding box {}", bbox);
+                }
+                let mut widget = Self::new(station_map, config);
+                widget.view = bbox;
+                widget.home = bbox;
+                widget
+            }
+            None => Self::new(station_map, config),
+        };
+        if config.animate {
+            let animation = Animation::zoom_in(widget.view);
+            widget.view = animation.view();
+            widget.animation = Some(animation);
+        }
+        if let Some(ref path) = config.route_file {
+            if path.exists() {
+                widget.set_route(&route::load(path)?);
+            }
+        }
+        Ok(widget)
+    }
+
+    pub fn set_config(&mut self, config: &Config) {
+        self.stations_widget.set_config(config);
+        self.segments_widget.set_config(config);
+        self.cluster_cells = config.cluster_cells;
+        self.arrows = config.arrows;
+        self.theme = config.theme;
+    }
+
+    fn tick(&mut self) -> bool {
+        match self.animation {
+            Some(ref mut animation) => {
+                animation.frame += 1;
+                self.view = animation.view();
+                if animation.is_finished() {
+                    self.animation = None;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn finish_animation(&mut self) -> bool {
+        match self.animation.take() {
+            Some(animation) => {
+                self.view = animation.to;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Sets the route to the stations `ds100s`, missing stations are reported as warnings.
+    fn set_route(&mut self, ds100s: &[String]) {
+        self.route.clear();
+        for ds100 in ds100s {
+            match self
+                .station_map
+                .stations
+                .iter()
+                .position(|station| station.ds100 == *ds100)
+            {
+                Some(index) => self.route.push(index),
+                None => self
+                    .station_map
+                    .warnings
+                    .push(format!("Station '{}' of the route not found", ds100)),
+            }
+        }
+    }
+
+    fn add_to_route(&mut self) {
+        if let Some(index) = self.stations_widget.selected_index() {
+            if self.route.last() != Some(&index) {
+                self.route.push(index);
+            }
+        }
+    }
+
+    fn save_route(&self, config: &Config) -> Result<()> {
+        let path = config
+            .route_file
+            .as_ref()
+            .context("No route file given, start tie with '--route <file>'")?;
+        let ds100s: Vec<String> = self
+            .route
+            .iter()
+            .map(|&index| self.station_map.stations[index].ds100.clone())
+            .collect();
+        route::save(path, &ds100s)
+    }
+
+    /// Opens a list of the stations connected to the selected station.
+    fn show_neighbors(&mut self, config: &Config) {
+        let station = match self
+            .stations_widget
+            .selected_index()
+            .and_then(|index| self.station_map.stations.get(index))
+        {
+            Some(station) => station,
+            None => return,
+        };
+
+        let stations: Vec<usize> = self
+            .station_map
+            .neighbors(&station.ds100, self.undirected)
+            .iter()
+            .filter_map(|neighbor| {
+                self.station_map
+                    .stations
+                    .iter()
+                    .position(|station| station.ds100 == neighbor.ds100)
+            })
+            .collect();
+        let names = stations
+            .iter()
+            .map(|&index| self.stations_widget.names[index].clone())
+            .collect();
+        let mut list =
+            ListSelectionWidget::new(format!("Nachbarn von {}", station.ds100), names, config);
+        list.focused = true;
+        self.neighbors = Some(NeighborsPopup { list, stations });
+    }
+
+    /// Handles `command` while the neighbors popup is open, `Select` jumps to the neighbor.
+    fn select_neighbors_command(&mut self, command: Command) {
+        let popup = match self.neighbors {
+            Some(ref mut popup) => popup,
+            None => return,
+        };
+        match command {
+            Command::Select => {
+                if let Some(index) = popup.list.selected_index() {
+                    let station = popup.stations[index];
+                    self.stations_widget.select_index(station);
+                    self.widget_selection = WidgetSelection::Stations;
+                }
+                self.neighbors = None;
+            }
+            Command::Back => self.neighbors = None,
+            _ => popup.list.select_command(command),
+        }
+    }
+
+    /// Zooms the map to the route, or to the stations of the focused list. The segments list
+    /// contributes the stations of its visible segments.
+    fn fit_selection(&mut self) {
+        let stations = &self.station_map.stations;
+        let coords: Vec<(f64, f64)> = if !self.route.is_empty() {
+            self.route
+                .iter()
+                .map(|&index| stations[index].coord)
+                .collect()
+        } else if self.widget_selection == WidgetSelection::Segments {
+            let segments = &self.station_map.segments;
+            self.segments_widget
+                .visible
+                .iter()
+                .flat_map(|&index| [segments[index].from.coord, segments[index].to.coord])
+                .collect()
+        } else {
+            self.stations_widget
+                .visible
+                .iter()
+                .map(|&index| stations[index].coord)
+                .collect()
+        };
+        if coords.is_empty() {
+            return;
+        }
+
+        self.animation = None;
+        self.view = calc_extent(&coords).scaled(1.0 + FIT_MARGIN);
+    }
+
+    /// Whether keys edit the station search or the route filter instead of running commands.
+    pub fn is_searching(&self) -> bool {
+        self.search.is_some() || self.route_filtering
+    }
+
+    fn open_search(&mut self) {
+        self.search = Some(Search {
+            query: String::new(),
+            view: self.view,
+            selected: self.stations_widget.selected_index(),
+        });
+        self.widget_selection = WidgetSelection::Stations;
+        self.update_search();
+    }
+
+    /// Shows the stations whose `ds100` or name match the query and centers the map on the
+    /// selected one.
+    fn update_search(&mut self) {
+        let (title, query) = match self.search {
+            Some(ref search) => (
+                format!("{} /{}", STATIONS_TITLE, search.query),
+                fold(&search.query),
+            ),
+            None => return,
+        };
+        let stations = &self.station_map.stations;
+        let visible = (0..stations.len())
+            .filter(|&index| {
+                fuzzy_matches(&query, &fold(&stations[index].ds100))
+                    || fuzzy_matches(&query, &fold(&stations[index].longname))
+            })
+            .collect();
+        self.stations_widget.set_visible(visible);
+        self.stations_widget.title = title;
+        self.center_on_selected_station();
+    }
+
+    /// Closes the station search and shows all stations again. A cancelled search restores the
+    /// view and selection from before the search, otherwise the found station stays selected.
+    fn close_search(&mut self, cancel: bool) {
+        let search = match self.search.take() {
+            Some(search) => search,
+            None => return,
+        };
+        self.stations_widget
+            .set_visible((0..self.station_map.stations.len()).collect());
+        self.stations_widget.title = STATIONS_TITLE.to_string();
+        if cancel {
+            self.view = search.view;
+            if let Some(index) = search.selected {
+                self.stations_widget.select_index(index);
+            }
+        }
+    }
+
+    /// Edits the station search: `Enter` keeps the found station selected, `Esc` cancels the
+    /// search and the arrow keys browse `history`.
+    pub fn select_key(&mut self, key: Key, history: &mut History) {
+        if self.route_filtering {
+            self.select_route_filter_key(key, history);
+            return;
+        }
+        let search = match self.search {
+            Some(ref mut search) => search,
+            None => return,
+        };
+        match key {
+            Key::Esc => {
+                history.reset();
+                self.close_search(true);
+            }
+            Key::Char('\n') => {
+                history.push(&search.query);
+                self.close_search(false);
+            }
+            Key::Up | Key::Ctrl('p') => {
+                if let Some(query) = history.prev() {
+                    search.query = query.to_string();
+                    self.update_search();
+                }
+            }
+            Key::Down | Key::Ctrl('n') => {
+                search.query = history.next().unwrap_or_default().to_string();
+                self.update_search();
+            }
+            Key::Backspace => {
+                search.query.pop();
+                self.update_search();
+            }
+            Key::Char(c) if !c.is_control() => {
+                search.query.push(c);
+                self.update_search();
+            }
+            _ => {}
+        }
+    }
+
+    /// Edits the route filter: `Enter` keeps the filter, `Esc` clears it and the arrow keys
+    /// browse `history`.
+    fn select_route_filter_key(&mut self, key: Key, history: &mut History) {
+        match key {
+            Key::Esc => {
+                history.reset();
+                self.route_filtering = false;
+                self.set_route_query(String::new());
+            }
+            Key::Char('\n') => {
+                history.push(&self.route_query);
+                self.route_filtering = false;
+                self.filter_segments();
+            }
+            Key::Up | Key::Ctrl('p') => {
+                if let Some(query) = history.prev() {
+                    self.set_route_query(query.to_string());
+                }
+            }
+            Key::Down | Key::Ctrl('n') => {
+                self.set_route_query(history.next().unwrap_or_default().to_string())
+            }
+            Key::Backspace => {
+                let mut query = self.route_query.clone();
+                query.pop();
+                self.set_route_query(query);
+            }
+            Key::Char(c) if !c.is_control() => {
+                let query = format!("{}{}", self.route_query, c);
+                self.set_route_query(query);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn paste(&mut self, text: &str) {
+        if self.route_filtering {
+            let query = format!("{}{}", self.route_query, text);
+            self.set_route_query(query);
+        } else if let Some(ref mut search) = self.search {
+            search.query.push_str(text);
+            self.update_search();
+        }
+    }
+
+    /// Bookmarks the selected station, or removes its bookmark.
+    fn toggle_bookmark(&mut self) {
+        let stations = &self.station_map.stations;
+        let ds100 = match self
+            .stations_widget
+            .selected_index()
+            .and_then(|index| stations.get(index))
+        {
+            Some(station) => &station.ds100,
+            None => return,
+        };
+        if !self.bookmarks.remove(ds100) {
+            self.bookmarks.insert(ds100.clone());
+        }
+    }
+
+    /// Selects and centers the next bookmarked station of the stations list after the selected
+    /// one, or the previous one if not `forward`. The search continues at the other end.
+    fn jump_to_bookmark(&mut self, forward: bool) {
+        let list = &self.stations_widget;
+        let len = list.visible.len();
+        let start = list.selected.unwrap_or(0);
+        let found = (1..=len)
+            .map(|step| {
+                if forward {
+                    (start + step) % len
+                } else {
+                    (start + len - step) % len
+                }
+            })
+            .find(|&position| {
+                let station = &self.station_map.stations[list.visible[position]];
+                self.bookmarks.contains(&station.ds100)
+            });
+        if let Some(position) = found {
+            self.stations_widget.selected = Some(position);
+            self.widget_selection = WidgetSelection::Stations;
+            self.center_on_selected_station();
+        }
+    }
+
+    /// Moves the view so that the selected station is in its center, the zoom stays.
+    fn center_on_selected_station(&mut self) {
+        let station = match self
+            .stations_widget
+            .selected_index()
+            .and_then(|index| self.station_map.stations.get(index))
+        {
+            Some(station) => station,
+            None => return,
+        };
+        let (x, y) = station.coord;
+        self.animation = None;
+        self.view = self.view.translated(
+            x - (self.view.min_x + self.view.max_x) / 2.0,
+            y - (self.view.min_y + self.view.max_y) / 2.0,
+        );
+    }
+
+    /// Scales the view by `factor` around its center, a factor below `1.0` zooms in.
+    fn zoom(&mut self, factor: f64) {
+        self.finish_animation();
+        self.view = self.view.scaled(factor);
+    }
+
+    fn toggle_histogram(&mut self) {
+        self.histogram = match self.histogram {
+            Some(_) => None,
+            None => Some(route_histogram(&self.station_map)),
+        };
+    }
+
+    fn toggle_electrification_filter(&mut self) {
+        self.electrification_filter = self.electrification_filter.next();
+        self.filter_segments();
+    }
+
+    /// Shows the segments matching the electrification filter and the route query.
+    fn filter_segments(&mut self) {
+        let filter = self.electrification_filter;
+        let query = self.route_query.as_str();
+        let segments = &self.station_map.segments;
+        let visible = (0..segments.len())
+            .filter(|&index| {
+                let segment = &segments[index];
+                filter.matches(segment) && segment.routenumber.to_string().starts_with(query)
+            })
+            .collect();
+
+        self.segments_widget.set_visible(visible);
+        self.segments_widget.title = if self.route_filtering || !query.is_empty() {
+            format!("{} /{}", filter.title(), query)
+        } else {
+            filter.title().to_string()
+        };
+    }
+
+    fn set_route_query(&mut self, query: String) {
+        self.route_query = query;
+        self.filter_segments();
+    }
+
+    pub fn select_command(&mut self, command: Command, config: &Config) -> Result<Option<Action>> {
+        if self.neighbors.is_some() {
+            self.select_neighbors_command(command);
+            return Ok(None);
+        }
+        if self.stats.is_some() {
+            if let Command::Back | Command::ShowStats = command {
+                self.stats = None;
+            }
+            return Ok(None);
+        }
+
+        match command {
+            Command::FocusStations => self.widget_selection = WidgetSelection::Stations,
+            Command::FocusSegments => self.widget_selection = WidgetSelection::Segments,
+            Command::FocusMap => self.widget_selection = WidgetSelection::Map,
+            Command::CycleFocus => {
+                self.widget_selection = match self.widget_selection {
+                    WidgetSelection::Stations => WidgetSelection::Segments,
+                    WidgetSelection::Segments => WidgetSelection::Map,
+                    WidgetSelection::Map => WidgetSelection::Stations,
+                }
+            }
+            Command::CycleFocusBack => {
+                self.widget_selection = match self.widget_selection {
+                    WidgetSelection::Stations => WidgetSelection::Map,
+                    WidgetSelection::Segments => WidgetSelection::Stations,
+                    WidgetSelection::Map => WidgetSelection::Segments,
+                }
+            }
+            Command::ToggleElectrification => self.toggle_electrification_filter(),
+            Command::AddToRoute => self.add_to_route(),
+            Command::SaveRoute => self.save_route(config)?,
+            Command::ToggleHistogram => self.toggle_histogram(),
+            Command::ShowNeighbors => self.show_neighbors(config),
+            Command::ShowStats => self.stats = Some(self.station_map.stats()),
+            Command::ToggleStationsLayer => self.show_stations = !self.show_stations,
+            Command::ToggleSegmentsLayer => self.show_segments = !self.show_segments,
+            Command::FitSelection => self.fit_selection(),
+            Command::ToggleDirected => self.undirected = !self.undirected,
+            Command::Filter if self.widget_selection == WidgetSelection::Segments => {
+                self.route_filtering = true;
+                self.filter_segments();
+            }
+            Command::Filter => self.open_search(),
+            Command::ToggleLabels => self.labels = !self.labels,
+            Command::ToggleBookmark => self.toggle_bookmark(),
+            Command::NextBookmark => self.jump_to_bookmark(true),
+            Command::PreviousBookmark => self.jump_to_bookmark(false),
+            Command::ZoomIn => self.zoom(1.0 / ZOOM_STEP),
+            Command::ZoomOut => self.zoom(ZOOM_STEP),
+            Command::ResetZoom => {
+                self.animation = None;
+                self.view = self.home;
+            }
+            Command::Back if !self.route_query.is_empty() => self.set_route_query(String::new()),
+            Command::Back => return Ok(Some(Action::Back)),
+            _ => {}
+        }
+
+        match self.widget_selection {
+            WidgetSelection::Stations => self.stations_widget.select_command(command),
+            WidgetSelection::Segments => self.segments_widget.select_command(command),
+            WidgetSelection::Map => match command {
+                Command::Up => self.pan(0.0, -PAN_STEP),
+                Command::Down => self.pan(0.0, PAN_STEP),
+                Command::Left => self.pan(-PAN_STEP, 0.0),
+                Command::Right => self.pan(PAN_STEP, 0.0),
+                _ => {}
+            },
+        }
+        Ok(None)
+    }
+
+    pub fn select_mouse(&mut self, event: MouseEvent) -> Option<Action> {
+        self.stations_widget.select_mouse(event);
+        self.segments_widget.select_mouse(event);
+
+        match event {
+            MouseEvent::Press(MouseButton::Left, x, y) if contains(self.map_area, x, y) => {
+                self.drag_start = Some((x, y));
+                self.click(x, y);
+            }
+            MouseEvent::Hold(x, y) => {
+                if let Some((start_x, start_y)) = self.drag_start {
+                    self.drag(
+                        f64::from(x) - f64::from(start_x),
+                        f64::from(y) - f64::from(start_y),
+                    );
+                    self.drag_start = Some((x, y));
+                }
+            }
+            MouseEvent::Release(_, _) => self.drag_start = None,
+            _ => {}
+        }
+        None
+    }
+
+    /// Selects the station nearest to a double click.
+    fn click(&mut self, x: u16, y: u16) {
+        let now = Instant::now();
+        let double_click = self.last_click.is_some_and(|(time, last_x, last_y)| {
+            now.duration_since(time) <= DOUBLE_CLICK_INTERVAL
+                && (i32::from(x) - i32::from(last_x)).abs() <= 1
+                && (i32::from(y) - i32::from(last_y)).abs() <= 1
+        });
+
+        if !double_click {
+            self.last_click = Some((now, x, y));
+            return;
+        }
+        self.last_click = None;
+
+        let (data_x, data_y) = self.to_data(x, y);
+        if let Some((index, distance)) = self.nearest_station_index(data_x, data_y) {
+            if distance <= DOUBLE_CLICK_RADIUS {
+                self.stations_widget.select_index(index);
+                self.widget_selection = WidgetSelection::Stations;
+            }
+        }
+    }
+
+    /// The data coordinates at the center of the one-based mouse position `x`, `y`.
+    fn to_data(&self, x: u16, y: u16) -> (f64, f64) {
+        let (cell_width, cell_height) = self.cell_size();
+        let column = f64::from(x.saturating_sub(1)) - f64::from(self.map_area.left()) + 0.5;
+        let row = f64::from(y.saturating_sub(1)) - f64::from(self.map_area.top()) + 0.5;
+        let data_row = row * cell_height;
+        let bounds = self.bounds();
+        (
+            bounds.min_x + column * cell_width,
+            if self.invert_y {
+                bounds.min_y + data_row
+            } else {
+                bounds.max_y - data_row
+            },
+        )
+    }
+
+    /// The width and height of a map cell in data units.
+    fn cell_size(&self) -> (f64, f64) {
+        let bounds = self.bounds();
+        (
+            bounds.width() / f64::from(self.map_area.width.max(1)),
+            bounds.height() / f64::from(self.map_area.height.max(1)),
+        )
+    }
+
+    /// The part of the map that is drawn: the view with its shorter axis padded, so that a km
+    /// is as long across as up, with cells twice as high as wide. For coordinates in degrees a
+    /// degree of longitude is shortened by the cosine of the latitude, other coordinates are
+    /// taken to be equally scaled on both axes.
+    fn bounds(&self) -> Extent {
+        let view = self.view;
+        if self.map_area.width == 0
+            || self.map_area.height == 0
+            || view.width() <= 0.0
+            || view.height() <= 0.0
+        {
+            return view;
+        }
+        let center_y = (view.min_y + view.max_y) / 2.0;
+        let longitude_scale = if (-90.0..=90.0).contains(&center_y) {
+            center_y.to_radians().cos().max(0.01)
+        } else {
+            1.0
+        };
+        // the data height of a cell per data width of a cell that keeps the aspect ratio
+        let ratio = CELL_ASPECT * longitude_scale;
+        let cell_width = view.width() / f64::from(self.map_area.width);
+        let cell_height = view.height() / f64::from(self.map_area.height);
+        if cell_height < ratio * cell_width {
+            view.scaled_xy(1.0, ratio * cell_width / cell_height)
+        } else {
+            view.scaled_xy(cell_height / (ratio * cell_width), 1.0)
+        }
+    }
+
+    /// The data coordinates under the one-based mouse position `x`, `y` formatted with the
+    /// decimals a cell resolves, `None` outside the map.
+    pub fn cursor_position(&self, x: u16, y: u16) -> Option<String> {
+        if !contains(self.map_area, x, y) {
+            return None;
+        }
+        let (data_x, data_y) = self.to_data(x, y);
+        let (cell_width, cell_height) = self.cell_size();
+        let decimals = |cell: f64| (-cell.log10()).ceil().clamp(0.0, 10.0) as usize;
+        Some(format!(
+            "x: {:.*}  y: {:.*}",
+            decimals(cell_width),
+            data_x,
+            decimals(cell_height),
+            data_y
+        ))
+    }
+
+    /// Writes the ds100 right of the stations in `bounds` if there are fewer than `MAX_LABELS`,
+    /// the label of the `selected` station is always written. Canvas labels would have to be
+    /// `'static`, so they are written to the buffer after the canvas.
+    fn draw_labels(&self, bounds: Extent, selected: Option<&String>, buf: &mut Buffer) {
+        let area = self.map_area;
+        if area.width < 2 || area.height == 0 {
+            return;
+        }
+        let visible: Vec<&Station> = self
+            .station_map
+            .stations
+            .iter()
+            .filter(|station| bounds.contains(station.coord))
+            .collect();
+        let all = visible.len() < MAX_LABELS;
+
+        for station in visible {
+            let is_selected = selected == Some(&station.ds100);
+            if !all && !is_selected {
+                continue;
+            }
+            // the cell the canvas draws the station in
+            let (x, y) = station.coord;
+            let column = ((x - bounds.min_x) * f64::from(area.width - 1) / bounds.width()) as u16;
+            let row_fraction = if self.invert_y {
+                y - bounds.min_y
+            } else {
+                bounds.max_y - y
+            } / bounds.height();
+            let row = (row_fraction * f64::from(area.height - 1)) as u16;
+            let color = if is_selected {
+                self.theme.selected_station
+            } else {
+                self.theme.label
+            };
+            if column + 1 < area.width {
+                buf.set_stringn(
+                    area.left() + column + 1,
+                    area.top() + row,
+                    &station.ds100,
+                    usize::from(area.width - column - 1),
+                    Style::default().fg(color),
+                );
+            }
+        }
+    }
+
+    /// The direction of the y axis on screen, `1.0` if larger y coordinates are drawn higher up.
+    fn y_direction(&self) -> f64 {
+        if self.invert_y {
+            -1.0
+        } else {
+            1.0
+        }
+    }
+
+    /// The index of the station nearest to `x`, `y` on the screen and its distance in cells.
+    /// Cells are higher than wide, so the distance in data units would prefer stations above
+    /// or below.
+    fn nearest_station_index(&self, x: f64, y: f64) -> Option<(usize, f64)> {
+        let (cell_width, cell_height) = self.cell_size();
+        self.station_tree
+            .nearest(x, y, (1.0 / cell_width, 1.0 / cell_height))
+            .map(|(index, squared)| (index, squared.sqrt()))
+    }
+
+    /// Pans the view so that the map follows a mouse drag of `dx`, `dy` cells.
+    fn drag(&mut self, dx: f64, dy: f64) {
+        if self.map_area.width == 0 || self.map_area.height == 0 {
+            return;
+        }
+
+        // the canvas y axis points up, the terminal y axis down
+        let (scale_x, scale_y) = self.cell_size();
+        self.translate(-dx * scale_x, self.y_direction() * dy * scale_y);
+    }
+
+    /// Pans the view by `dx`, `dy` times its size, a positive `dy` moves it down the screen.
+    fn pan(&mut self, dx: f64, dy: f64) {
+        self.finish_animation();
+        self.translate(
+            dx * self.view.width(),
+            -self.y_direction() * dy * self.view.height(),
+        );
+    }
+
+    /// Moves the view by `dx`, `dy` in data units, its center stays inside the map that opened.
+    fn translate(&mut self, dx: f64, dy: f64) {
+        let center_x = (self.view.min_x + self.view.max_x) / 2.0;
+        let center_y = (self.view.min_y + self.view.max_y) / 2.0;
+        let dx = (center_x + dx).clamp(self.home.min_x, self.home.max_x) - center_x;
+        let dy = (center_y + dy).clamp(self.home.min_y, self.home.max_y) - center_y;
+        self.view = self.view.translated(dx, dy);
+    }
+}
+
+impl Widget for MapWidget {
+    fn draw(&mut self, area: Rect, buf: &mut Buffer) {
+        let h_chunks = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints(vec![Constraint::Percentage(30), Constraint::Percentage(70)])
+            .split(area);
+
+        let left_rect = h_chunks[0];
+        let mut right_rect = h_chunks[1];
+
+        if let Some(ref histogram) = self.histogram {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints(vec![Constraint::Percentage(70), Constraint::Percentage(30)])
+                .split(right_rect);
+            right_rect = chunks[0];
+
+            let data: Vec<(&str, u64)> = histogram
+                .iter()
+                .map(|(label, count)| (label.as_str(), *count))
+                .collect();
+            BarChart::default()
+                .block(
+                    Block::default()
+                        .title("Segmente je Streckennummer")
+                        .borders(Borders::ALL),
+                )
+                .data(&data)
+                .bar_width(HISTOGRAM_BAR_WIDTH)
+                .bar_gap(1)
+                .style(Style::default().fg(self.theme.histogram))
+                .value_style(Style::default().fg(Color::Black).bg(self.theme.histogram))
+                .draw(chunks[1], buf);
+        }
+
+        let v_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(left_rect);
+
+        let top_left = v_chunks[0];
+        let bottom_left = v_chunks[1];
+
+        self.stations_widget.focused = self.widget_selection == WidgetSelection::Stations;
+        self.segments_widget.focused = self.widget_selection == WidgetSelection::Segments;
+        self.stations_widget.draw(top_left, buf);
+        self.segments_widget.draw(bottom_left, buf);
+        self.map_area = Block::default().borders(Borders::ALL).inner(right_rect);
+
+        let selected_station: Option<&Station> = self
+            .stations_widget
+            .selected_index()
+            .and_then(|index| self.station_map.stations.get(index));
+        // the segments of the list, narrowed by the electrification and the route filter. They
+        // limit the incident segments as well
+        let listed_segments = &self.segments_widget.visible;
+        let all_listed = listed_segments.len() == self.station_map.segments.len();
+        let incident: Vec<usize> = self
+            .stations_widget
+            .selected_index()
+            .and_then(|index| self.incident_segments.get(index))
+            .map_or(Vec::new(), |segments| {
+                segments
+                    .iter()
+                    .cloned()
+                    .filter(|index| all_listed || listed_segments.contains(index))
+                    .collect()
+            });
+        let selected_segment: Option<&Segment> = self
+            .segments_widget
+            .selected_index()
+            .and_then(|index| self.station_map.segments.get(index));
+        let segments = &self.station_map.segments;
+        let stations = &self.station_map.stations;
+        let route = &self.route;
+
+        // the canvas can't invert its y bounds, so the coordinates are mirrored inside the view
+        let view = self.bounds();
+        let invert_y = self.invert_y;
+        let y = |y: f64| {
+            if invert_y {
+                view.min_y + view.max_y - y
+            } else {
+                y
+            }
+        };
+        let point = |(px, py): (f64, f64)| (px, y(py));
+        // data units per cell, arrowheads are sized in cells
+        let scale = (
+            view.width() / f64::from(self.map_area.width.max(1)),
+            view.height() / f64::from(self.map_area.height.max(1)),
+        );
+        let arrows = self.arrows;
+        let theme = &self.theme;
+
+        let station_points: Vec<(f64, f64)> =
+            lod_coordinates(&self.coordinates, view, self.map_area, self.cluster_cells)
+                .into_iter()
+                .map(point)
+                .collect();
+
+        let isolated_points: Vec<(f64, f64)> = self
+            .isolated
+            .iter()
+            .cloned()
+            .filter(|&coord| view.contains(coord))
+            .map(point)
+            .collect();
+
+        let bookmark_points: Vec<(f64, f64)> = self
+            .station_map
+            .stations
+            .iter()
+            .filter(|station| {
+                self.bookmarks.contains(&station.ds100) && view.contains(station.coord)
+            })
+            .map(|station| point(station.coord))
+            .collect();
+
+        let mut title = match self.station_map.warnings.len() {
+            0 => "Karte".to_string(),
+            1 => "Karte (1 Warnung)".to_string(),
+            n => format!("Karte ({} Warnungen)", n),
+        };
+        if !self.undirected {
+            title = format!("{} - gerichtet", title);
+        }
+        if !self.bookmarks.is_empty() {
+            title = format!("{} - {} Lesezeichen", title, self.bookmarks.len());
+        }
+        if let Some(segment) = selected_segment {
+            // straight-line segments, so the length is approximate
+            title = format!(
+                "{} - Strecke {}: ≈{:.1} km",
+                title,
+                segment.routenumber,
+                self.station_map.route_length_km(segment.routenumber)
+            );
+        }
+
+        let map_border_style = if self.widget_selection == WidgetSelection::Map {
+            Style::default().fg(self.theme.focus)
+        } else {
+            Style::default()
+        };
+
+        Canvas::default()
+            .block(
+                Block::default()
+                    .title(&title)
+                    .borders(Borders::ALL)
+                    .border_style(map_border_style),
+            )
+            .x_bounds([view.min_x, view.max_x])
+            .y_bounds([view.min_y, view.max_y])
+            .paint(|ctx| {
+                // base layers, highlights are always drawn. Segments outside the view are
+                // skipped, drawing a line costs time even if all its points are clipped
+                if self.show_segments {
+                    for segment in listed_segments
+                        .iter()
+                        .map(|&index| &segments[index])
+                        .filter(|segment| {
+                            view.intersects_line(segment.from.coord, segment.to.coord)
+                        })
+                    {
+                        ctx.draw(&Line {
+                            x1: segment.from.coord.0,
+                            y1: y(segment.from.coord.1),
+                            x2: segment.to.coord.0,
+                            y2: y(segment.to.coord.1),
+                            color: electrification_color(segment, theme),
+                        });
+                        if arrows {
+                            draw_arrow(
+                                ctx,
+                                point(segment.from.coord),
+                                point(segment.to.coord),
+                                electrification_color(segment, theme),
+                                scale,
+                            );
+                        }
+                    }
+                }
+
+                if self.show_stations {
+                    ctx.layer();
+                    ctx.draw(&Points {
+                        coords: &station_points[..],
+                        color: theme.station,
+                    });
+                    // stations without segments hint at incomplete data
+                    ctx.layer();
+                    ctx.draw(&Points {
+                        coords: &isolated_points[..],
+                        color: theme.isolated_station,
+                    });
+                }
+
+                // bookmarks are highlights, they are drawn without the stations layer too
+                if !bookmark_points.is_empty() {
+                    ctx.layer();
+                    ctx.draw(&Points {
+                        coords: &bookmark_points[..],
+                        color: theme.bookmark,
+                    });
+                }
+
+                if !route.is_empty() {
+                    ctx.layer();
+                    for leg in route.windows(2) {
+                        let (from, to) = (&stations[leg[0]], &stations[leg[1]]);
+                        if !view.intersects_line(from.coord, to.coord) {
+                            continue;
+                        }
+                        ctx.draw(&Line {
+                            x1: from.coord.0,
+                            y1: y(from.coord.1),
+                            x2: to.coord.0,
+                            y2: y(to.coord.1),
+                            color: theme.route,
+                        });
+                    }
+                    let coords: Vec<(f64, f64)> = route
+                        .iter()
+                        .map(|&index| stations[index].coord)
+                        .filter(|&coord| view.contains(coord))
+                        .map(point)
+                        .collect();
+                    ctx.draw(&Points {
+                        coords: &coords[..],
+                        color: theme.route,
+                    });
+                }
+
+                if !incident.is_empty() {
+                    ctx.layer();
+                    for &index in &incident {
+                        let segment = &segments[index];
+                        if !view.intersects_line(segment.from.coord, segment.to.coord) {
+                            continue;
+                        }
+                        ctx.draw(&Line {
+                            x1: segment.from.coord.0,
+                            y1: y(segment.from.coord.1),
+                            x2: segment.to.coord.0,
+                            y2: y(segment.to.coord.1),
+                            color: theme.incident_segment,
+                        });
+                    }
+                }
+
+                if let Some(station) = selected_station {
+                    ctx.layer();
+                    ctx.draw(&Points {
+                        coords: &[point(station.coord)],
+                        color: theme.selected_station,
+                    });
+                }
+
+                if let Some(segment) = selected_segment {
+                    ctx.layer();
+                    ctx.draw(&Line {
+                        x1: segment.from.coord.0,
+                        y1: y(segment.from.coord.1),
+                        x2: segment.to.coord.0,
+                        y2: y(segment.to.coord.1),
+                        color: theme.selected_segment,
+                    });
+                    draw_arrow(
+                        ctx,
+                        point(segment.from.coord),
+                        point(segment.to.coord),
+                        theme.selected_segment,
+                        scale,
+                    );
+                }
+            })
+            .draw(right_rect, buf);
+
+        if self.labels {
+            self.draw_labels(view, selected_station.map(|station| &station.ds100), buf);
+        }
 
-pub enum TermWidget {
-    InfrastrukturSelection(InfrastrukturSelectionWidget),
-    Map(MapWidget),
-}
+        if let Some(ref stats) = self.stats {
+            let rect = centered_rect(40, 40, area);
+            clear(rect, buf);
+            let lines = [
+                format!("Betriebsstellen: {}\n", stats.stations),
+                format!("Streckensegmente: {}\n", stats.segments),
+                format!("Streckennummern: {}\n", stats.routes),
+                format!("Isolierte Betriebsstellen: {}\n", stats.isolated),
+                format!("Ausdehnung: {}\n", stats.extent),
+            ];
+            let text: Vec<Text> = lines.iter().map(Text::raw).collect();
+            Paragraph::new(text.iter())
+                .block(
+                    Block::default()
+                        .title(&self.station_map.name)
+                        .borders(Borders::ALL)
+                        .border_style(Style::default().fg(self.theme.focus)),
+                )
+                .wrap(true)
+                .draw(rect, buf);
+        }
 
-impl TermWidget {
-    pub fn render<B>(&mut self, f: &mut Frame<B>, area: Rect)
-    where
-        Self: Sized,
-        B: Backend,
-    {
-        match *self {
-            TermWidget::InfrastrukturSelection(ref mut widget) => widget.render(f, area),
-            TermWidget::Map(ref mut widget) => widget.render(f, area),
+        if let Some(ref mut popup) = self.neighbors {
+            let rect = centered_rect(40, 40, area);
+            clear(rect, buf);
+            popup.list.draw(rect, buf);
         }
     }
 }
 
-//
-// InfrastrukturSelectionWidget
-//
-
-pub struct InfrastrukturSelectionWidget {
-    values: Vec<InfrastrukturInfo>,
-    items: Vec<String>,
+/// The query of the station search and the view and selection it started with.
+struct Search {
+    query: String,
+    view: Extent,
     selected: Option<usize>,
 }
 
-impl InfrastrukturSelectionWidget {
-    pub fn new(values: Vec<InfrastrukturInfo>) -> Self {
-        let items = values
-            .iter()
-            .map(|index| format!("{}: {}", index.id, index.anzeigename))
-            .collect::<Vec<_>>();
+/// Whether the characters of `query` appear in `text` in the same order, e.g. `hbf` in
+/// `hamburg-altona bf`. Both have to be folded.
+fn fuzzy_matches(query: &str, text: &str) -> bool {
+    let mut chars = text.chars();
+    query.chars().all(|q| chars.any(|c| c == q))
+}
 
-        let selected = if values.is_empty() {
-            None
-        } else {
-            Some(0usize)
-        };
+/// The stations connected to a station, `list` shows them and `stations` holds their indices.
+struct NeighborsPopup {
+    list: ListSelectionWidget,
+    stations: Vec<usize>,
+}
 
-        InfrastrukturSelectionWidget {
-            values,
-            items,
-            selected,
+/// The indices of the segments starting or ending at every station of `station_map`.
+fn incident_segments(station_map: &StationMap) -> Vec<Vec<usize>> {
+    let indices: HashMap<&str, usize> = station_map
+        .stations
+        .iter()
+        .enumerate()
+        .map(|(index, station)| (station.ds100.as_str(), index))
+        .collect();
+    let mut incident = vec![Vec::new(); station_map.stations.len()];
+    for (index, segment) in station_map.segments.iter().enumerate() {
+        for ds100 in [&segment.from.ds100, &segment.to.ds100] {
+            if let Some(&station) = indices.get(ds100.as_str()) {
+                if incident[station].last() != Some(&index) {
+                    incident[station].push(index);
+                }
+            }
         }
     }
+    incident
+}
+
+/// Map width in cells per cell of the default cluster grid.
+const CLUSTER_CELLS_PER_WIDTH: u16 = 80;
 
-    pub fn from_url(url: &str) -> Result<Self> {
-        Ok(Self::new(read_infrastructure_infos(url)?))
+/// Level of detail for the station points. If `view` holds more stations than `area` has cells,
+/// the stations are clustered on a grid of `cluster_cells` and drawn as one point per cluster.
+fn lod_coordinates(
+    coords: &[(f64, f64)],
+    view: Extent,
+    area: Rect,
+    cluster_cells: Option<u16>,
+) -> Vec<(f64, f64)> {
+    let visible: Vec<(f64, f64)> = coords
+        .iter()
+        .cloned()
+        .filter(|&coord| view.contains(coord))
+        .collect();
+    if area.width == 0
+        || area.height == 0
+        || visible.len() <= usize::from(area.width) * usize::from(area.height)
+    {
+        return visible;
     }
 
-    fn up(&mut self) {
-        self.selected = up(&self.values, self.selected);
+    let cells = cluster_cells
+        .unwrap_or(area.width / CLUSTER_CELLS_PER_WIDTH)
+        .max(1);
+    let cell_width = view.width() / f64::from(area.width) * f64::from(cells);
+    let cell_height = view.height() / f64::from(area.height) * f64::from(cells);
+
+    // the centroid of every cluster
+    let mut clusters: BTreeMap<(i64, i64), (f64, f64, usize)> = BTreeMap::new();
+    for (x, y) in visible {
+        let cell = (
+            ((x - view.min_x) / cell_width) as i64,
+            ((y - view.min_y) / cell_height) as i64,
+        );
+        let cluster = clusters.entry(cell).or_insert((0.0, 0.0, 0));
+        cluster.0 += x;
+        cluster.1 += y;
+        cluster.2 += 1;
     }
+    clusters
+        .values()
+        .map(|&(x, y, count)| (x / count as f64, y / count as f64))
+        .collect()
+}
 
-    fn down(&mut self) {
-        self.selected = down(&self.values, self.selected);
+/// Number of route numbers shown in the histogram, the others are summed up in one bar.
+const HISTOGRAM_BARS: usize = 10;
+
+const HISTOGRAM_BAR_WIDTH: u16 = 6;
+
+/// The bars of the route number histogram, the most frequent route numbers first.
+fn route_histogram(station_map: &StationMap) -> Vec<(String, u64)> {
+    let routes = station_map.routes();
+    let mut bars: Vec<(String, u64)> = routes
+        .iter()
+        .take(HISTOGRAM_BARS)
+        .map(|&(routenumber, count)| (routenumber.to_string(), count as u64))
+        .collect();
+    let other: usize = routes
+        .iter()
+        .skip(HISTOGRAM_BARS)
+        .map(|&(_, count)| count)
+        .sum();
+    if other > 0 {
+        bars.push(("Andere".to_string(), other as u64));
     }
+    bars
+}
 
-    fn selected_value(&self) -> Option<&InfrastrukturInfo> {
-        if let Some(index) = self.selected {
-            Some(&self.values[index])
-        } else {
-            None
-        }
+/// Length of the arrowhead wings in cells.
+const ARROW_CELLS: f64 = 1.5;
+
+/// Angle between the wings of an arrowhead and the segment.
+const ARROW_ANGLE: f64 = std::f64::consts::PI / 6.0;
+
+/// Draws an arrowhead at `to` pointing away from `from`. `scale` holds the data units per cell,
+/// so the arrowhead has the same size in every direction and at every zoom level.
+fn draw_arrow(
+    ctx: &mut canvas::Context,
+    from: (f64, f64),
+    to: (f64, f64),
+    color: Color,
+    scale: (f64, f64),
+) {
+    let (scale_x, scale_y) = scale;
+    // in cells, cells are about twice as high as wide
+    let dx = (to.0 - from.0) / scale_x;
+    let dy = (to.1 - from.1) / scale_y * 2.0;
+    if dx == 0.0 && dy == 0.0 {
+        return;
     }
 
-    pub fn select_key(&mut self, key: Key, api_url: &str) -> Result<Option<TermWidget>> {
-        match key {
-            Key::Up => self.up(),
-            Key::Down => self.down(),
-            Key::Char('\n') /* enter */ => {
-                if let Some(info) = self.selected_value() {
-                    return Ok(Some(TermWidget::Map(MapWidget::from_url(api_url, info.id)?)));
-                }
-            }
-            _ => {}
-        }
-        Ok(None)
+    let back = (-dy).atan2(-dx);
+    for wing in &[back - ARROW_ANGLE, back + ARROW_ANGLE] {
+        ctx.draw(&Line {
+            x1: to.0,
+            y1: to.1,
+            x2: to.0 + wing.cos() * ARROW_CELLS * scale_x,
+            y2: to.1 + wing.sin() * ARROW_CELLS / 2.0 * scale_y,
+            color,
+        });
     }
 }
 
-impl Widget for InfrastrukturSelectionWidget {
-    fn draw(&mut self, area: Rect, buf: &mut Buffer) {
-        let rect = Layout::default()
-            .direction(Direction::Horizontal)
-            .constraints(vec![
-                Constraint::Percentage(30),
-                Constraint::Percentage(40),
-                Constraint::Percentage(30),
-            ])
-            .split(area)[1];
-
-        let rect = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints(vec![
-                Constraint::Percentage(30),
-                Constraint::Percentage(40),
-                Constraint::Percentage(30),
-            ])
-            .split(rect)[1];
+/// The list item of `segment`, like `1234 (ABC -> XYZ, 5.2 km)`.
+fn segment_name(segment: &Segment) -> String {
+    format!(
+        "{} ({} -> {}, {:.1} km)",
+        segment.routenumber,
+        segment.from.ds100,
+        segment.to.ds100,
+        segment.length_km()
+    )
+}
 
-        SelectableList::default()
-            .block(
-                Block::default()
-                    .title("Infrastrukturen")
-                    .borders(Borders::ALL),
-            )
-            .items(&self.items[..])
-            .style(Style::default().fg(Color::White))
-            .highlight_style(Style::default().modifier(Modifier::BOLD))
-            .select(self.selected)
-            .draw(rect, buf);
+fn electrification_color(segment: &Segment, theme: &Theme) -> Color {
+    match segment.electrified {
+        Some(true) => theme.electrified_segment,
+        Some(false) => theme.not_electrified_segment,
+        None => theme.segment,
     }
 }
 
 //
-// MapWidget
+// Animation
 //
 
-#[derive(PartialEq)]
-enum WidgetSelection {
-    Stations,
-    Segments,
-}
-
-pub struct MapWidget {
-    station_map: StationMap,
-    coordinates: Vec<(f64, f64)>,
-    extent: Extent,
+const ANIMATION_FRAMES: u32 = 10;
 
-    stations_widget: ListSelectionWidget,
-    segments_widget: ListSelectionWidget,
+/// How far a map is zoomed out when the zoom in animation starts.
+const ANIMATION_ZOOM: f64 = 3.0;
 
-    widget_selection: WidgetSelection,
+/// Interpolates the view of a map from `from` to `to` over `ANIMATION_FRAMES` ticks.
+struct Animation {
+    from: Extent,
+    to: Extent,
+    frame: u32,
 }
 
-impl MapWidget {
-    pub fn new(station_map: StationMap) -> Self {
-        let station_names: Vec<String> = station_map
-            .stations
-            .iter()
-            .map(|station| format!("{} ({})", station.ds100, station.longname))
-            .collect();
-
-        let stations_widget: ListSelectionWidget =
-            ListSelectionWidget::new("Betriebsstellen".to_string(), station_names);
-
-        let segment_names: Vec<String> = station_map
-            .segments
-            .iter()
-            .map(|segment| {
-                format!(
-                    "{} ({} -> {})",
-                    segment.routenumber, segment.from.ds100, segment.to.ds100
-                )
-            })
-            .collect();
-
-        let segments_widget: ListSelectionWidget =
-            ListSelectionWidget::new("Streckensegmente".to_string(), segment_names);
+impl Animation {
+    fn zoom_in(to: Extent) -> Self {
+        Animation {
+            from: to.scaled(ANIMATION_ZOOM),
+            to,
+            frame: 0,
+        }
+    }
 
-        let coordinates = station_map.coordinates();
-        let extent = calc_extent(&coordinates);
+    fn is_finished(&self) -> bool {
+        self.frame >= ANIMATION_FRAMES
+    }
 
-        MapWidget {
-            station_map,
-            coordinates,
-            extent,
-            stations_widget,
-            segments_widget,
-            widget_selection: WidgetSelection::Stations,
+    fn view(&self) -> Extent {
+        // ease out, fast at the start and slow at the end
+        let t = f64::from(self.frame.min(ANIMATION_FRAMES)) / f64::from(ANIMATION_FRAMES);
+        let t = 1.0 - (1.0 - t).powi(2);
+        let interpolate = |from: f64, to: f64| from + (to - from) * t;
+        Extent {
+            min_x: interpolate(self.from.min_x, self.to.min_x),
+            max_x: interpolate(self.from.max_x, self.to.max_x),
+            min_y: interpolate(self.from.min_y, self.to.min_y),
+            max_y: interpolate(self.from.max_y, self.to.max_y),
         }
     }
+}
 
-    pub fn from_url(bae_url: &str, id: u64) -> Result<Self> {
-        Ok(Self::new(read_station_map(bae_url, id)?))
-    }
+//
+// ErrorWidget
+//
 
-    pub fn select_key(&mut self, key: Key, api_url: &str) -> Result<Option<TermWidget>> {
-        match key {
-            Key::Char('b') => self.widget_selection = WidgetSelection::Stations,
-            Key::Char('s') => self.widget_selection = WidgetSelection::Segments,
-            Key::Esc => {
-                let widget = InfrastrukturSelectionWidget::from_url(api_url)?;
-                return Ok(Some(TermWidget::InfrastrukturSelection(widget)));
-            }
-            _ => {}
+/// Shows a failed action on top of the widget it failed in. Only errors with an action can be
+/// retried.
+pub struct ErrorWidget {
+    message: String,
+    action: Option<Action>,
+    attempts: u32,
+    previous: Box<TermWidget>,
+}
+
+impl ErrorWidget {
+    pub fn new(error: anyhow::Error, action: Option<Action>, previous: TermWidget) -> Self {
+        ErrorWidget {
+            message: format!("{:#}", error),
+            action,
+            attempts: 1,
+            previous: Box::new(previous),
         }
+    }
 
-        match self.widget_selection {
-            WidgetSelection::Stations => self.stations_widget.select_key(key),
-            WidgetSelection::Segments => self.segments_widget.select_key(key),
+    pub fn select_command(
+        mut self,
+        command: Command,
+        source: &DataSource,
+        config: &Config,
+        stack: &mut Vec<TermWidget>,
+    ) -> TermWidget {
+        match command {
+            Command::Retry => match self.action.take() {
+                Some(Action::Back) if !stack.is_empty() => stack.pop().unwrap(),
+                Some(action) => TermWidget::Loading(LoadingWidget::start(
+                    action,
+                    source,
+                    config,
+                    *self.previous,
+                    self.attempts,
+                )),
+                None => TermWidget::Error(self),
+            },
+            Command::Back => *self.previous,
+            _ => TermWidget::Error(self),
         }
     }
 }
 
-impl Widget for MapWidget {
+impl Widget for ErrorWidget {
     fn draw(&mut self, area: Rect, buf: &mut Buffer) {
-        let h_chunks = Layout::default()
-            .direction(Direction::Horizontal)
-            .constraints(vec![Constraint::Percentage(30), Constraint::Percentage(70)])
-            .split(area);
-
-        let left_rect = h_chunks[0];
-        let right_rect = h_chunks[1];
+        self.previous.draw(area, buf);
 
-        let v_chunks = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints(vec![Constraint::Percentage(50), Constraint::Percentage(50)])
-            .split(left_rect);
+        let rect = centered_rect(60, 30, area);
+        clear(rect, buf);
 
-        let top_left = v_chunks[0];
-        let bottom_left = v_chunks[1];
+        let hint = match self.action {
+            Some(_) => "\n\nr: Erneut versuchen, Esc: Zurück",
+            None => "\n\nEsc: Zurück",
+        };
+        let text = [
+            Text::styled(&self.message, Style::default().fg(Color::Red)),
+            Text::raw(hint),
+        ];
+        let title = match self.attempts {
+            1 => "Fehler".to_string(),
+            n => format!("Fehler ({} Versuche)", n),
+        };
+        Paragraph::new(text.iter())
+            .block(Block::default().title(&title).borders(Borders::ALL))
+            .wrap(true)
+            .draw(rect, buf);
+    }
+}
 
-        self.stations_widget.draw(top_left, buf);
-        self.segments_widget.draw(bottom_left, buf);
+//
+// LoadingWidget
+//
 
-        let selected_station: Option<&Station> = self
-            .stations_widget
-            .selected
-            .and_then(|index| self.station_map.stations.get(index));
-        let selected_segment: Option<&Segment> = self
-            .segments_widget
-            .selected
-            .and_then(|index| self.station_map.segments.get(index));
+/// Time a `LoadingWidget` shows only the previous widget, a fast action does not flash the
+/// loading box.
+const LOADING_DELAY: Duration = Duration::from_millis(200);
 
-        Canvas::default()
-            .block(Block::default().title("Karte").borders(Borders::ALL))
-            .x_bounds([self.extent.min_x, self.extent.max_x])
-            .y_bounds([self.extent.min_y, self.extent.max_y])
-            .paint(|ctx| {
-                ctx.draw(&Points {
-                    coords: &self.coordinates[..],
-                    color: Color::Blue,
-                });
+/// Runs an action on a background thread and shows a loading box on top of the widget it was
+/// started in until the action finished.
+pub struct LoadingWidget {
+    action: Action,
+    /// The failed attempts of the action before this one.
+    attempts: u32,
+    started: Instant,
+    /// The seconds the box shows, `None` while it is hidden.
+    shown: Option<u64>,
+    result: mpsc::Receiver<Result<TermWidget>>,
+    previous: Box<TermWidget>,
+}
 
-                if let Some(station) = selected_station {
-                    ctx.layer();
-                    ctx.draw(&Points {
-                        coords: &[(station.coord.0, station.coord.1)],
-                        color: Color::Red,
+impl LoadingWidget {
+    fn start(
+        action: Action,
+        source: &DataSource,
+        config: &Config,
+        previous: TermWidget,
+        attempts: u32,
+    ) -> Self {
+        let (sender, result) = mpsc::channel();
+        let thread_action = action.clone();
+        let source = source.clone();
+        let config = config.clone();
+        thread::spawn(move || {
+            let result =
+                panic::catch_unwind(AssertUnwindSafe(|| thread_action.run(&source, &config)))
+                    .unwrap_or_else(|payload| {
+                        Err(anyhow!("Loading failed: {}", panic_message(&*payload)))
                     });
-                }
+            // a cancelled widget dropped the receiver, the result is not needed anymore
+            let _ = sender.send(result);
+        });
 
-                if let Some(segment) = selected_segment {
-                    ctx.layer();
-                    ctx.draw(&Line {
-                        x1: segment.from.coord.0,
-                        y1: segment.from.coord.1,
-                        x2: segment.to.coord.0,
-                        y2: segment.to.coord.1,
-                        color: Color::Yellow,
-                    });
+        LoadingWidget {
+            action,
+            attempts,
+            started: Instant::now(),
+            shown: None,
+            result,
+            previous: Box::new(previous),
+        }
+    }
+
+    /// The next widget if the action finished, a failed action shows an `ErrorWidget`.
+    fn poll(self, stack: &mut Vec<TermWidget>) -> TermWidget {
+        let result = match self.result.try_recv() {
+            Ok(result) => result,
+            Err(mpsc::TryRecvError::Empty) => return TermWidget::Loading(self),
+            Err(mpsc::TryRecvError::Disconnected) => Err(anyhow!("Loading failed")),
+        };
+        match result {
+            Ok(next) => {
+                if !matches!(self.action, Action::Back) {
+                    stack.push(*self.previous);
                 }
-            })
-            .draw(right_rect, buf);
+                next
+            }
+            Err(error) => {
+                let mut widget = ErrorWidget::new(error, Some(self.action), *self.previous);
+                widget.attempts += self.attempts;
+                TermWidget::Error(widget)
+            }
+        }
+    }
+
+    /// The widget the action was started in.
+    pub fn previous(&self) -> &TermWidget {
+        &self.previous
+    }
+
+    /// `Back` cancels the action and returns to the previous widget.
+    fn select_command(self, command: Command) -> TermWidget {
+        match command {
+            Command::Back => *self.previous,
+            _ => TermWidget::Loading(self),
+        }
+    }
+
+    /// Returns whether the box appeared or its seconds changed.
+    fn tick(&mut self) -> bool {
+        let elapsed = self.started.elapsed();
+        let shown = Some(elapsed.as_secs()).filter(|_| elapsed >= LOADING_DELAY);
+        let changed = shown != self.shown;
+        self.shown = shown;
+        changed || self.previous.tick()
+    }
+
+    fn title(&self) -> String {
+        match self.action {
+            Action::FetchInfos | Action::Back => "Infrastrukturen werden geladen…".to_string(),
+            Action::FetchMap(ref ids) => format!(
+                "Infrastruktur {} wird geladen…",
+                ids.iter()
+                    .map(|id| id.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Action::OpenFile(ref path) => format!("'{}' wird geladen…", path.display()),
+        }
     }
 }
 
-#[derive(Debug)]
-struct Extent {
-    pub min_x: f64,
-    pub max_x: f64,
-    pub min_y: f64,
-    pub max_y: f64,
+/// The message of a panic `payload`, `panic!` passes a `&str` or a `String`.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
 }
 
-fn calc_extent(coords: &[(f64, f64)]) -> Extent {
-    // x coordinates
-    let x_coords: Vec<OrderedFloat<f64>> =
-        coords.iter().map(|coord| OrderedFloat(coord.0)).collect();
-    let y_coords: Vec<OrderedFloat<f64>> =
-        coords.iter().map(|coord| OrderedFloat(coord.1)).collect();
+impl Widget for LoadingWidget {
+    fn draw(&mut self, area: Rect, buf: &mut Buffer) {
+        self.previous.draw(area, buf);
+        let seconds = match self.shown {
+            Some(seconds) => seconds,
+            None => return,
+        };
 
-    Extent {
-        min_x: x_coords.iter().min().unwrap().clone().into(),
-        max_x: x_coords.iter().max().unwrap().clone().into(),
-        min_y: y_coords.iter().min().unwrap().clone().into(),
-        max_y: y_coords.iter().max().unwrap().clone().into(),
+        let rect = centered_rect(50, 30, area);
+        clear(rect, buf);
+
+        let text = [
+            Text::raw(format!("{}\n", self.title())),
+            Text::styled(
+                format!("{} s", seconds),
+                Style::default().fg(Color::DarkGray),
+            ),
+            Text::raw("\n\nEsc: Abbrechen"),
+        ];
+        Paragraph::new(text.iter())
+            .block(Block::default().title("Laden").borders(Borders::ALL))
+            .wrap(true)
+            .draw(rect, buf);
     }
 }
 
@@ -304,47 +2762,166 @@ fn calc_extent(coords: &[(f64, f64)]) -> Extent {
 struct ListSelectionWidget {
     title: String,
     names: Vec<String>,
-    selected: Option<usize>,
+    visible: Vec<usize>,     // indices into names
+    selected: Option<usize>, // index into visible
+    sorting: Sorting,
+    highlight: Style,
+    highlight_symbol: &'static str,
+    theme: Theme,
+    /// See `InfrastrukturSelectionWidget::wrap`.
+    wrap: bool,
+    focused: bool,
+    area: Rect,
 }
 
 impl Widget for ListSelectionWidget {
     fn draw(&mut self, area: Rect, buf: &mut Buffer) {
+        self.area = area;
+        let width = list_width(area, self.highlight_symbol);
+        let items: Vec<String> = self
+            .visible
+            .iter()
+            .map(|&index| truncate_to_width(&self.names[index], width))
+            .collect();
+        let title = format!("{}{}", self.title, self.sorting.title_suffix());
+        let border_style = if self.focused {
+            Style::default().fg(self.theme.focus)
+        } else {
+            Style::default()
+        };
+
         SelectableList::default()
-            .block(Block::default().title(&self.title).borders(Borders::ALL))
-            .items(&self.names[..])
-            .style(Style::default().fg(Color::White))
-            .highlight_style(Style::default().modifier(Modifier::BOLD))
+            .block(
+                Block::default()
+                    .title(&title)
+                    .borders(Borders::ALL)
+                    .border_style(border_style),
+            )
+            .items(&items[..])
+            .style(Style::default().fg(self.theme.list))
+            .highlight_style(self.highlight)
+            .highlight_symbol(self.highlight_symbol)
             .select(self.selected)
             .draw(area, buf);
     }
 }
 
 impl ListSelectionWidget {
-    pub fn new(title: String, names: Vec<String>) -> Self {
+    pub fn new(title: String, names: Vec<String>, config: &Config) -> Self {
         let selected = if names.is_empty() { None } else { Some(0usize) };
+        let visible = (0..names.len()).collect();
 
         ListSelectionWidget {
             title,
             names,
+            visible,
             selected,
+            sorting: Sorting::default(),
+            highlight: config.highlight_style(),
+            highlight_symbol: config.highlight_symbol(),
+            theme: config.theme,
+            wrap: config.wrap,
+            focused: false,
+            area: Rect::default(),
+        }
+    }
+
+    pub fn set_config(&mut self, config: &Config) {
+        self.highlight = config.highlight_style();
+        self.highlight_symbol = config.highlight_symbol();
+        self.theme = config.theme;
+        self.wrap = config.wrap;
+    }
+
+    /// The index into `names` of the selected item.
+    fn selected_index(&self) -> Option<usize> {
+        self.selected
+            .and_then(|index| self.visible.get(index).cloned())
+    }
+
+    /// Selects the item at `index` into `names`, if it is visible.
+    fn select_index(&mut self, index: usize) {
+        if let Some(position) = self.visible.iter().position(|&i| i == index) {
+            self.selected = Some(position);
+        }
+    }
+
+    /// Shows only the `names` at the `visible` indices. The selected item stays selected if it
+    /// is still visible, otherwise the item at the same position (or the last one) is selected.
+    fn set_visible(&mut self, visible: Vec<usize>) {
+        let selected_index = self.selected_index();
+        let position = self.selected.unwrap_or(0);
+        self.visible = visible;
+        self.sort_visible();
+        self.selected = selected_index
+            .and_then(|selected_index| self.visible.iter().position(|&i| i == selected_index))
+            .or_else(|| match self.visible.len() {
+                0 => None,
+                len => Some(position.min(len - 1)),
+            });
+    }
+
+    /// Sorts the visible items with `sorting`, the selected item stays selected.
+    fn sort(&mut self, sorting: Sorting) {
+        let selected_index = self.selected_index();
+        self.sorting = sorting;
+        self.sort_visible();
+        if let Some(index) = selected_index {
+            self.select_index(index);
+        }
+    }
+
+    fn sort_visible(&mut self) {
+        let names = &self.names;
+        match self.sorting.key {
+            SortKey::Default => self.visible.sort_unstable(),
+            SortKey::Name => self
+                .visible
+                .sort_by(|&a, &b| names[a].cmp(&names[b]).then(a.cmp(&b))),
+        }
+        if self.sorting.reverse {
+            self.visible.reverse();
         }
     }
 
     fn up(&mut self) {
-        self.selected = up(&self.names, self.selected);
+        self.selected = if self.wrap {
+            up_wrap(&self.visible, self.selected)
+        } else {
+            up(&self.visible, self.selected)
+        };
     }
 
     fn down(&mut self) {
-        self.selected = down(&self.names, self.selected);
+        self.selected = if self.wrap {
+            down_wrap(&self.visible, self.selected)
+        } else {
+            down(&self.visible, self.selected)
+        };
     }
 
-    pub fn select_key(&mut self, key: Key) -> Result<Option<TermWidget>> {
-        match key {
-            Key::Up => self.up(),
-            Key::Down => self.down(),
+    pub fn select_command(&mut self, command: Command) {
+        match command {
+            Command::Up => self.up(),
+            Command::Down => self.down(),
+            Command::PageUp | Command::PageDown | Command::First | Command::Last => {
+                self.selected = jump(command, &self.visible, self.selected, self.area)
+            }
+            Command::CycleSort => self.sort(self.sorting.next_key()),
+            Command::ReverseSort => self.sort(self.sorting.reversed()),
+            _ => {}
+        }
+    }
+
+    /// Scrolls with the mouse wheel if the mouse is over the list.
+    pub fn select_mouse(&mut self, event: MouseEvent) {
+        match event {
+            MouseEvent::Press(MouseButton::WheelUp, x, y) if contains(self.area, x, y) => self.up(),
+            MouseEvent::Press(MouseButton::WheelDown, x, y) if contains(self.area, x, y) => {
+                self.down()
+            }
             _ => {}
         }
-        Ok(None)
     }
 }
 
@@ -364,6 +2941,41 @@ fn up<T>(values: &[T], selected: Option<usize>) -> Option<usize> {
     }
 }
 
+/// Rows a page moves by before a list is drawn.
+const PAGE_ROWS: usize = 10;
+
+/// The selection after a `PageUp`, `PageDown`, `First` or `Last` command in a bordered list
+/// drawn in `area`, a page is as high as the list.
+fn jump<T>(command: Command, values: &[T], selected: Option<usize>, area: Rect) -> Option<usize> {
+    let rows = match area.height {
+        0 => PAGE_ROWS,
+        height => usize::from(height.saturating_sub(2)).max(1),
+    };
+    match command {
+        Command::PageUp => (0..rows).fold(selected, |selected, _| up(values, selected)),
+        Command::PageDown => (0..rows).fold(selected, |selected, _| down(values, selected)),
+        Command::First => up(values, Some(0)),
+        Command::Last => down(values, Some(values.len().saturating_sub(1))),
+        _ => selected,
+    }
+}
+
+/// Like `up`, but the first item moves to the last one.
+fn up_wrap<T>(values: &[T], selected: Option<usize>) -> Option<usize> {
+    match selected {
+        Some(0) if !values.is_empty() => Some(values.len() - 1),
+        selected => up(values, selected),
+    }
+}
+
+/// Like `down`, but the last item moves to the first one.
+fn down_wrap<T>(values: &[T], selected: Option<usize>) -> Option<usize> {
+    match selected {
+        Some(index) if index + 1 == values.len() => Some(0),
+        selected => down(values, selected),
+    }
+}
+
 fn down<T>(values: &[T], selected: Option<usize>) -> Option<usize> {
     if values.is_empty() {
         return None;
@@ -380,3 +2992,131 @@ fn down<T>(values: &[T], selected: Option<usize>) -> Option<usize> {
         Some(0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::ApiDate;
+
+    fn info(id: u64, anzeigename: &str) -> InfrastrukturInfo {
+        InfrastrukturInfo {
+            id,
+            anzeigename: anzeigename.to_string(),
+            fahrplanjahr: 2020,
+            gueltig_von: ApiDate::from("2019-12-15".to_string()),
+            gueltig_bis: ApiDate::from("2020-12-12".to_string()),
+        }
+    }
+
+    fn selected_id(widget: &InfrastrukturSelectionWidget) -> Option<u64> {
+        widget.selected_value().map(|info| info.id)
+    }
+
+    #[test]
+    fn infrastructure_items_show_the_validity() {
+        assert_eq!(
+            infrastructure_items(&[info(1, "Alpha")]),
+            vec!["1: Alpha (15.12.2019 - 12.12.2020)"]
+        );
+    }
+
+    #[test]
+    fn filter_keeps_the_selection_visible() {
+        let values = vec![info(1, "Alpha"), info(2, "Beta"), info(3, "Gamma")];
+        let mut widget = InfrastrukturSelectionWidget::new(values, &Config::default());
+        widget.down();
+        widget.down();
+        assert_eq!(selected_id(&widget), Some(3));
+
+        // a surviving selection stays selected
+        widget.set_query("a".to_string());
+        assert_eq!(selected_id(&widget), Some(3));
+
+        // a hidden selection moves to a visible value
+        widget.set_query("alpha".to_string());
+        assert_eq!(widget.selected, Some(0));
+        assert_eq!(selected_id(&widget), Some(1));
+
+        widget.set_query("nothing".to_string());
+        assert_eq!(widget.selected, None);
+        assert_eq!(selected_id(&widget), None);
+
+        widget.set_query(String::new());
+        assert_eq!(widget.visible.len(), 3);
+        assert!(selected_id(&widget).is_some());
+    }
+
+    fn station(ds100: &str, coord: (f64, f64)) -> Station {
+        Station {
+            ds100: ds100.to_string(),
+            longname: ds100.to_string(),
+            coord,
+        }
+    }
+
+    #[test]
+    fn segment_name_shows_the_length_in_km() {
+        // 5.2 km along a meridian, a degree of latitude is 111.195 km
+        let segment = Segment {
+            from: station("ABC", (10.0, 50.0)),
+            to: station("XYZ", (10.0, 50.0 + 5.2 / 111.195)),
+            routenumber: 1234,
+            electrified: None,
+        };
+        assert!((segment.length_km() - 5.2).abs() < 0.01);
+        assert_eq!(segment_name(&segment), "1234 (ABC -> XYZ, 5.2 km)");
+
+        // Berlin Hbf to München Hbf, about 504 km
+        let segment = Segment {
+            from: station("BL", (13.3695, 52.5251)),
+            to: station("MH", (11.5586, 48.1402)),
+            routenumber: 6107,
+            electrified: Some(true),
+        };
+        assert!((segment.length_km() - 504.0).abs() < 2.0);
+        assert_eq!(segment_name(&segment), "6107 (BL -> MH, 504.2 km)");
+    }
+
+    #[test]
+    fn truncate_to_width_measures_display_width() {
+        // exact width and shorter strings are kept
+        assert_eq!(truncate_to_width("München", 7), "München");
+        assert_eq!(truncate_to_width("Köln – Bonn", 20), "Köln – Bonn");
+        assert_eq!(truncate_to_width("München", 6), "Münch…");
+        assert_eq!(truncate_to_width("München", 0), "");
+        assert_eq!(truncate_to_width("München", 1), "…");
+
+        // wide characters take two cells and are never cut in half
+        assert_eq!(truncate_to_width("日本語", 6), "日本語");
+        assert_eq!(truncate_to_width("日本語", 5), "日本…");
+        assert_eq!(truncate_to_width("日本語", 4), "日…");
+        assert_eq!(truncate_to_width("日本語", 4).width(), 3);
+
+        // combining characters take no cell and stay with their base character
+        let combining = "Cafe\u{301} Bar";
+        assert_eq!(combining.width(), 8);
+        assert_eq!(truncate_to_width(combining, 8), combining);
+        assert_eq!(truncate_to_width(combining, 5), "Cafe\u{301}…");
+    }
+
+    #[test]
+    fn set_visible_keeps_the_selection_visible() {
+        let names = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let mut widget = ListSelectionWidget::new("Liste".to_string(), names, &Config::default());
+        widget.select_index(2);
+
+        widget.set_visible(vec![1, 2]);
+        assert_eq!(widget.selected_index(), Some(2));
+
+        widget.set_visible(vec![0]);
+        assert_eq!(widget.selected, Some(0));
+        assert_eq!(widget.selected_index(), Some(0));
+
+        widget.set_visible(Vec::new());
+        assert_eq!(widget.selected, None);
+        assert_eq!(widget.selected_index(), None);
+
+        widget.set_visible(vec![0, 1, 2]);
+        assert_eq!(widget.selected_index(), Some(0));
+    }
+}